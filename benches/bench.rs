@@ -1,4 +1,6 @@
-use self::common::{bench_compute_hash, bench_get, bench_insert};
+use self::common::{
+    bench_build, bench_compute_hash, bench_get, bench_get_32, bench_get_deep, bench_insert,
+};
 use criterion::{criterion_group, criterion_main, Criterion};
 use sha3::Keccak256;
 use std::time::Duration;
@@ -21,11 +23,26 @@ fn criterion_benchmark(c: &mut Criterion) {
         .bench_function("100k", bench_get::<100_000>())
         .bench_function("1M", bench_get::<1_000_000>());
 
+    c.benchmark_group("get() from a tree made with paths at the maximum depth")
+        .bench_function("1k", bench_get_deep::<1_000>())
+        .bench_function("10k", bench_get_deep::<10_000>())
+        .bench_function("100k", bench_get_deep::<100_000>());
+
+    c.benchmark_group("get() from a tree made with random 32-byte paths")
+        .bench_function("get_bytes() 1M", bench_get_32::<1_000_000>(false))
+        .bench_function("get_fixed() 1M", bench_get_32::<1_000_000>(true));
+
     c.benchmark_group("insert() from a tree made with random values")
         .bench_function("1k", bench_insert::<1_000>())
         .bench_function("10k", bench_insert::<10_000>())
         .bench_function("100k", bench_insert::<100_000>())
         .bench_function("1M", bench_insert::<1_000_000>());
+
+    c.benchmark_group("build a tree made with random values with its capacity preallocated")
+        .bench_function("with_capacity() 10k", bench_build::<10_000>(true))
+        .bench_function("new() + reserve() 10k", bench_build::<10_000>(false))
+        .bench_function("with_capacity() 100k", bench_build::<100_000>(true))
+        .bench_function("new() + reserve() 100k", bench_build::<100_000>(false));
 }
 
 criterion_group!(benches, criterion_benchmark);
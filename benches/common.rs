@@ -32,6 +32,67 @@ pub fn bench_get<const N: usize>() -> impl FnMut(&mut Bencher) {
     }
 }
 
+pub fn bench_get_32<const N: usize>(fixed: bool) -> impl FnMut(&mut Bencher) {
+    // Generate a Patricia Merkle tree made of random 32-byte paths.
+    let mut tree = PatriciaMerkleTree::<Vec<u8>, &[u8; 32], Keccak256>::new();
+    let mut all_paths = Vec::with_capacity(N);
+
+    let value = &[0; 32];
+
+    let mut rng = thread_rng();
+    while all_paths.len() < N {
+        let mut path = [0; 32];
+        rng.fill_bytes(&mut path);
+
+        if tree.insert(path.to_vec(), value).is_none() {
+            all_paths.push(path);
+        }
+    }
+
+    move |b| {
+        let mut path_iter = all_paths.iter().cycle();
+        if fixed {
+            b.iter(|| tree.get_fixed(black_box(path_iter.next().unwrap())));
+        } else {
+            b.iter(|| tree.get_bytes(black_box(path_iter.next().unwrap())));
+        }
+    }
+}
+
+pub fn bench_get_deep<const N: usize>() -> impl FnMut(&mut Bencher) {
+    // Generate a Patricia Merkle tree made of combs: random 32-byte paths along with a copy of each
+    // one for every nibble, differing only in that nibble. Looking up the random path (or any copy
+    // differing near its end) walks through a branch at every nibble depth up to 64.
+    let mut tree = PatriciaMerkleTree::<Vec<u8>, &[u8; 32], Keccak256>::new();
+    let mut deep_paths = Vec::with_capacity(3 * N / 64);
+
+    let value = &[0; 32];
+
+    let mut rng = thread_rng();
+    while tree.len() < N {
+        let mut path = vec![0; 32];
+        rng.fill_bytes(&mut path);
+
+        for nibble in 0..64 {
+            let mut other_path = path.clone();
+            other_path[nibble >> 1] ^= if nibble & 0x01 != 0 { 0x01 } else { 0x10 };
+
+            tree.insert(other_path.clone(), value);
+            if nibble >= 62 {
+                deep_paths.push(other_path);
+            }
+        }
+
+        tree.insert(path.clone(), value);
+        deep_paths.push(path);
+    }
+
+    move |b| {
+        let mut path_iter = deep_paths.iter().cycle();
+        b.iter(|| tree.get(black_box(path_iter.next().unwrap())));
+    }
+}
+
 pub fn bench_insert<const N: usize>() -> impl FnMut(&mut Bencher) {
     // Generate a completely random Patricia Merkle tree.
     let mut tree = PatriciaMerkleTree::<Vec<u8>, _, Keccak256>::new();
@@ -96,6 +157,48 @@ pub fn bench_insert<const N: usize>() -> impl FnMut(&mut Bencher) {
     }
 }
 
+pub fn bench_build<const N: usize>(with_capacity: bool) -> impl FnMut(&mut Bencher) {
+    // Generate random paths to build a tree from scratch.
+    let mut all_paths = Vec::with_capacity(N);
+
+    let value = &[0; 32];
+
+    let mut rng = thread_rng();
+    let distr = Uniform::from(16..=64);
+
+    while all_paths.len() < N {
+        let path_len = distr.sample(&mut rng) as usize;
+
+        let mut path = vec![0; path_len];
+        rng.fill_bytes(&mut path);
+        all_paths.push(path);
+    }
+
+    move |b| {
+        b.iter_custom(|num_iters| {
+            let mut delta = Duration::ZERO;
+            for _ in 0..num_iters {
+                let all_paths = all_paths.clone();
+
+                let measure = Instant::now();
+                let mut tree = if with_capacity {
+                    PatriciaMerkleTree::<Vec<u8>, _, Keccak256>::with_capacity(N)
+                } else {
+                    let mut tree = PatriciaMerkleTree::<Vec<u8>, _, Keccak256>::new();
+                    tree.reserve(N);
+                    tree
+                };
+                for path in all_paths {
+                    tree.insert(black_box(path), black_box(value));
+                }
+                delta += measure.elapsed();
+            }
+
+            delta
+        });
+    }
+}
+
 pub fn bench_compute_hash<const N: usize, H: Digest + Clone>() -> impl FnMut(&mut Bencher) {
     let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, H>::new();
     let mut all_paths = Vec::with_capacity(N);
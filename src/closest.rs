@@ -0,0 +1,127 @@
+use crate::{nibble::NibbleSlice, node::Node, Encodable, NodeRef, NodesStorage, ValueRef};
+use digest::Digest;
+
+/// Find the value whose path shares the longest nibble prefix with `path`.
+///
+/// When several values share that prefix, the one whose diverging nibble is closest to the query's
+/// is chosen, with ties broken towards the smaller nibble. A path ending at the divergence point (a
+/// branch's value) diverges with a virtual nibble below zero. Within the chosen subtree, the
+/// smallest path is returned if it sorts after the query, and the biggest one otherwise.
+pub(crate) fn find_closest<P, V, H>(
+    nodes: &NodesStorage<P, V, H>,
+    root_ref: NodeRef,
+    mut path: NibbleSlice,
+) -> Option<ValueRef>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    let mut node_ref = root_ref;
+    loop {
+        match nodes.get(*node_ref)? {
+            Node::Branch(branch_node) => {
+                let query = match path.next() {
+                    Some(x) => x as usize,
+                    None => return Some(first_value_ref(nodes, node_ref)),
+                };
+
+                if branch_node.choices[query].is_valid() {
+                    node_ref = branch_node.choices[query];
+                    continue;
+                }
+
+                let (choice, child_ref) = branch_node
+                    .choices
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child_ref)| child_ref.is_valid())
+                    .min_by_key(|(choice, _)| (choice.abs_diff(query), *choice))
+                    .expect("inconsistent internal tree structure");
+
+                // The value's distance is `query + 1`, and it wins ties since it's the smallest.
+                return Some(
+                    if branch_node.value_ref.is_valid() && query < choice.abs_diff(query) {
+                        branch_node.value_ref
+                    } else if choice > query {
+                        first_value_ref(nodes, *child_ref)
+                    } else {
+                        last_value_ref(nodes, *child_ref)
+                    },
+                );
+            }
+            Node::Extension(extension_node) => {
+                for nibble in extension_node.prefix.iter() {
+                    match path.next() {
+                        Some(query) if query == nibble => {}
+                        Some(query) if query > nibble => {
+                            return Some(last_value_ref(nodes, node_ref))
+                        }
+                        _ => return Some(first_value_ref(nodes, node_ref)),
+                    }
+                }
+
+                node_ref = extension_node.child_ref;
+            }
+            Node::Leaf(leaf_node) => return Some(leaf_node.value_ref),
+        }
+    }
+}
+
+/// Return the value with the smallest path within a subtree.
+fn first_value_ref<P, V, H>(nodes: &NodesStorage<P, V, H>, mut node_ref: NodeRef) -> ValueRef
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    loop {
+        match nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure")
+        {
+            Node::Branch(branch_node) => {
+                if branch_node.value_ref.is_valid() {
+                    return branch_node.value_ref;
+                }
+
+                node_ref = *branch_node
+                    .choices
+                    .iter()
+                    .find(|child_ref| child_ref.is_valid())
+                    .expect("inconsistent internal tree structure");
+            }
+            Node::Extension(extension_node) => node_ref = extension_node.child_ref,
+            Node::Leaf(leaf_node) => return leaf_node.value_ref,
+        }
+    }
+}
+
+/// Return the value with the biggest path within a subtree.
+fn last_value_ref<P, V, H>(nodes: &NodesStorage<P, V, H>, mut node_ref: NodeRef) -> ValueRef
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    loop {
+        match nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure")
+        {
+            Node::Branch(branch_node) => {
+                match branch_node
+                    .choices
+                    .iter()
+                    .rev()
+                    .find(|child_ref| child_ref.is_valid())
+                {
+                    Some(child_ref) => node_ref = *child_ref,
+                    None => return branch_node.value_ref,
+                }
+            }
+            Node::Extension(extension_node) => node_ref = extension_node.child_ref,
+            Node::Leaf(leaf_node) => return leaf_node.value_ref,
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{nibble::NibbleSlice, node::Node, Encodable, NodeRef, NodesStorage, ValuesStorage};
+use digest::Digest;
+
+/// Number of positions within a branch: its value, then its 16 children.
+const BRANCH_SLOTS: usize = 17;
+
+/// Cursor over the paths and values of a tree, which can move in both directions.
+///
+/// It keeps the path from the root to its position, so moving to a neighbour doesn't walk again
+/// from the root. When not positioned at any entry, moving forward goes to the first one and moving
+/// backward goes to the last one.
+pub struct Cursor<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    nodes: &'a NodesStorage<P, V, H>,
+    values: &'a ValuesStorage<P, V>,
+    root_ref: NodeRef,
+
+    /// The nodes from the root to the current entry, along with the position within each of them.
+    /// Positions within branches are zero for their value and `n + 1` for their `n`th child.
+    stack: Vec<(NodeRef, usize)>,
+}
+
+impl<'a, P, V, H> Cursor<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(
+        nodes: &'a NodesStorage<P, V, H>,
+        values: &'a ValuesStorage<P, V>,
+        root_ref: NodeRef,
+    ) -> Self {
+        Self {
+            nodes,
+            values,
+            root_ref,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Return the path of the current entry.
+    pub fn key(&self) -> Option<&'a [u8]> {
+        self.current().map(|(path, _)| path)
+    }
+
+    /// Return the value of the current entry.
+    pub fn value(&self) -> Option<&'a V> {
+        self.current().map(|(_, value)| value)
+    }
+
+    /// Move to the previous entry and return it, or become unpositioned if there's none.
+    pub fn prev(&mut self) -> Option<(&'a [u8], &'a V)> {
+        if self.stack.is_empty() {
+            if self.root_ref.is_valid() {
+                self.descend_last(self.root_ref);
+            }
+
+            return self.current();
+        }
+
+        while let Some((node_ref, slot)) = self.stack.pop() {
+            if let Node::Branch(branch_node) = self.node(node_ref) {
+                if let Some(choice) = (0..slot.saturating_sub(1))
+                    .rev()
+                    .find(|&choice| branch_node.choices[choice].is_valid())
+                {
+                    self.stack.push((node_ref, choice + 1));
+                    self.descend_last(branch_node.choices[choice]);
+                    return self.current();
+                }
+
+                if slot != 0 && branch_node.value_ref.is_valid() {
+                    self.stack.push((node_ref, 0));
+                    return self.current();
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Move to the first entry whose path is not smaller than `path` and return it, or become
+    /// unpositioned if there's none.
+    pub fn seek(&mut self, path: &[u8]) -> Option<(&'a [u8], &'a V)> {
+        self.stack.clear();
+
+        let mut query = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        while node_ref.is_valid() {
+            match self.node(node_ref) {
+                Node::Branch(branch_node) => {
+                    let choice = match query.next() {
+                        Some(x) => x as usize,
+                        None => {
+                            // Every path in the subtree is at least as big as the query.
+                            self.descend_first(node_ref);
+                            return self.current();
+                        }
+                    };
+
+                    if branch_node.choices[choice].is_valid() {
+                        self.stack.push((node_ref, choice + 1));
+                        node_ref = branch_node.choices[choice];
+                        continue;
+                    }
+
+                    return match (choice..16).find(|&x| branch_node.choices[x].is_valid()) {
+                        Some(choice) => {
+                            self.stack.push((node_ref, choice + 1));
+                            self.descend_first(branch_node.choices[choice]);
+                            self.current()
+                        }
+                        None => {
+                            self.stack.push((node_ref, BRANCH_SLOTS));
+                            self.next()
+                        }
+                    };
+                }
+                Node::Extension(extension_node) => {
+                    for nibble in extension_node.prefix.iter() {
+                        match query.next() {
+                            Some(x) if x == nibble => {}
+                            Some(x) if x > nibble => {
+                                // Every path in the subtree is smaller than the query.
+                                self.stack.push((node_ref, 0));
+                                return self.next();
+                            }
+                            _ => {
+                                self.descend_first(node_ref);
+                                return self.current();
+                            }
+                        }
+                    }
+
+                    self.stack.push((node_ref, 0));
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => {
+                    self.stack.push((node_ref, 0));
+
+                    let (value_path, _) = self
+                        .values
+                        .get(*leaf_node.value_ref)
+                        .expect("inconsistent internal tree structure");
+                    return if value_path.as_ref() >= path {
+                        self.current()
+                    } else {
+                        self.next()
+                    };
+                }
+            }
+        }
+
+        None
+    }
+
+    fn node(&self, node_ref: NodeRef) -> &'a Node<P, V, H> {
+        self.nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure")
+    }
+
+    fn current(&self) -> Option<(&'a [u8], &'a V)> {
+        let (node_ref, _) = *self.stack.last()?;
+        let value_ref = match self.node(node_ref) {
+            Node::Branch(branch_node) => branch_node.value_ref,
+            Node::Leaf(leaf_node) => leaf_node.value_ref,
+            Node::Extension(_) => panic!("inconsistent internal tree structure"),
+        };
+
+        let (path, value) = self
+            .values
+            .get(*value_ref)
+            .expect("inconsistent internal tree structure");
+        Some((path.as_ref(), value))
+    }
+
+    /// Push the path to the smallest entry within a subtree.
+    fn descend_first(&mut self, mut node_ref: NodeRef) {
+        loop {
+            match self.node(node_ref) {
+                Node::Branch(branch_node) => {
+                    if branch_node.value_ref.is_valid() {
+                        self.stack.push((node_ref, 0));
+                        return;
+                    }
+
+                    let choice = branch_node
+                        .choices
+                        .iter()
+                        .position(NodeRef::is_valid)
+                        .expect("inconsistent internal tree structure");
+                    self.stack.push((node_ref, choice + 1));
+                    node_ref = branch_node.choices[choice];
+                }
+                Node::Extension(extension_node) => {
+                    self.stack.push((node_ref, 0));
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(_) => {
+                    self.stack.push((node_ref, 0));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Push the path to the biggest entry within a subtree.
+    fn descend_last(&mut self, mut node_ref: NodeRef) {
+        loop {
+            match self.node(node_ref) {
+                Node::Branch(branch_node) => {
+                    match branch_node.choices.iter().rposition(NodeRef::is_valid) {
+                        Some(choice) => {
+                            self.stack.push((node_ref, choice + 1));
+                            node_ref = branch_node.choices[choice];
+                        }
+                        None => {
+                            self.stack.push((node_ref, 0));
+                            return;
+                        }
+                    }
+                }
+                Node::Extension(extension_node) => {
+                    self.stack.push((node_ref, 0));
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(_) => {
+                    self.stack.push((node_ref, 0));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, P, V, H> Iterator for Cursor<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = (&'a [u8], &'a V);
+
+    /// Move to the next entry and return it, or become unpositioned if there's none.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stack.is_empty() {
+            if self.root_ref.is_valid() {
+                self.descend_first(self.root_ref);
+            }
+
+            return self.current();
+        }
+
+        while let Some((node_ref, slot)) = self.stack.pop() {
+            if let Node::Branch(branch_node) = self.node(node_ref) {
+                // Slot `n + 1` is the `n`th child, therefore the following children start at `slot`.
+                if let Some(choice) =
+                    (slot..16).find(|&choice| branch_node.choices[choice].is_valid())
+                {
+                    self.stack.push((node_ref, choice + 1));
+                    self.descend_first(branch_node.choices[choice]);
+                    return self.current();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::PatriciaMerkleTree;
+    use sha3::Keccak256;
+
+    const PATHS: [&[u8]; 7] = [
+        &[0x12],
+        &[0x12, 0x34],
+        &[0x12, 0x34, 0x56],
+        &[0x12, 0x35],
+        &[0x12, 0xF0],
+        &[0x56],
+        &[0x56, 0x78, 0x9A],
+    ];
+
+    fn build_tree() -> PatriciaMerkleTree<&'static [u8], &'static [u8], Keccak256> {
+        let mut tree = PatriciaMerkleTree::new();
+        for path in PATHS.iter().rev() {
+            tree.insert(*path, *path);
+        }
+
+        tree
+    }
+
+    #[test]
+    fn cursor_forward_backward() {
+        let tree = build_tree();
+        let mut cursor = tree.cursor();
+        assert_eq!(cursor.key(), None);
+
+        let mut forward = Vec::new();
+        while let Some((path, value)) = cursor.next() {
+            assert_eq!(path, *value);
+            assert_eq!(cursor.key(), Some(path));
+            forward.push(path);
+        }
+        assert_eq!(forward, PATHS);
+        assert_eq!(cursor.value(), None);
+
+        let mut backward = Vec::new();
+        while let Some((path, _)) = cursor.prev() {
+            backward.push(path);
+        }
+        assert_eq!(backward, PATHS.iter().rev().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cursor_change_direction() {
+        let tree = build_tree();
+        let mut cursor = tree.cursor();
+
+        assert_eq!(
+            cursor.seek(&[0x12, 0x34, 0x56]),
+            Some((PATHS[2], &PATHS[2]))
+        );
+        assert_eq!(cursor.next().map(|(x, _)| x), Some(PATHS[3]));
+        assert_eq!(cursor.prev().map(|(x, _)| x), Some(PATHS[2]));
+        assert_eq!(cursor.prev().map(|(x, _)| x), Some(PATHS[1]));
+        assert_eq!(cursor.prev().map(|(x, _)| x), Some(PATHS[0]));
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.prev().map(|(x, _)| x), Some(PATHS[6]));
+    }
+
+    #[test]
+    fn cursor_seek() {
+        let tree = build_tree();
+        let mut cursor = tree.cursor();
+
+        let mut seek = |path: &[u8]| cursor.seek(path).map(|(x, _)| x);
+        assert_eq!(seek(&[]), Some(PATHS[0]));
+        assert_eq!(seek(&[0x12]), Some(PATHS[0]));
+        assert_eq!(seek(&[0x12, 0x00]), Some(PATHS[1]));
+        assert_eq!(seek(&[0x12, 0x34, 0x00]), Some(PATHS[2]));
+        assert_eq!(seek(&[0x12, 0x34, 0x57]), Some(PATHS[3]));
+        assert_eq!(seek(&[0x12, 0x36]), Some(PATHS[4]));
+        assert_eq!(seek(&[0x12, 0xF1]), Some(PATHS[5]));
+        assert_eq!(seek(&[0x13]), Some(PATHS[5]));
+        assert_eq!(seek(&[0x56, 0x00]), Some(PATHS[6]));
+        assert_eq!(seek(&[0x56, 0x78, 0x9B]), None);
+        assert_eq!(seek(&[0xFF]), None);
+    }
+
+    #[test]
+    fn cursor_empty() {
+        let tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        let mut cursor = tree.cursor();
+
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.seek(&[0x12]), None);
+    }
+}
@@ -1,15 +1,16 @@
 use crate::{
+    hashing::INLINE_THRESHOLD,
     node::Node,
     nodes::{BranchNode, ExtensionNode, LeafNode},
-    NodeRef, PatriciaMerkleTree,
+    Encodable, NodeRef, PatriciaMerkleTree,
 };
 use digest::Digest;
-use std::io::Write;
+use std::{fmt::Write as _, io::Write};
 
 pub struct TreeDump<'a, P, V, H, W>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
     W: Write,
 {
@@ -22,7 +23,7 @@ where
 impl<'a, P, V, H, W> TreeDump<'a, P, V, H, W>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
     W: Write,
 {
@@ -88,7 +89,7 @@ where
                 .expect("inconsistent internal tree structure");
 
             let key = key.as_ref();
-            let value = value.as_ref();
+            let value = value.encoded();
             write!(
                 self.writer,
                 "{indent}}} with_value {{ {key:02x?} => {value:02x?} }}"
@@ -120,7 +121,188 @@ where
             .expect("inconsistent internal tree structure");
 
         let key = key.as_ref();
-        let value = value.as_ref();
+        let value = value.encoded();
         write!(self.writer, "leaf {{ {key:02x?} => {value:02x?} }}").unwrap();
     }
 }
+
+impl<P, V, H> PatriciaMerkleTree<P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    /// Render the tree's structure as nested JSON objects, one per node.
+    ///
+    /// Every node has a `type`, its `path` from the root and its `hash` (as used within its parent,
+    /// therefore inlined encodings are rendered as they are). Branches have their `value` (or
+    /// `null`) and their `children` by nibble, extensions their `prefix` and `child`, and leaves
+    /// their `key` and `value`. Nibbles are rendered one digit each, bytes two. An empty tree is
+    /// rendered as `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        if self.root_ref.is_valid() {
+            self.write_json_node(&mut json, self.root_ref, &mut Vec::new());
+        } else {
+            json.push_str("null");
+        }
+
+        json
+    }
+
+    fn write_json_node(&self, json: &mut String, node_ref: NodeRef, path: &mut Vec<u8>) {
+        let node = self
+            .nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure");
+
+        let mut encoding = Vec::new();
+        node.encode_into(&self.nodes, &self.values, path.len(), &mut encoding);
+        let hash = if node_ref == self.root_ref || encoding.len() >= INLINE_THRESHOLD {
+            H::digest(&encoding).to_vec()
+        } else {
+            encoding
+        };
+
+        write!(
+            json,
+            "{{\"type\":\"{}\",\"path\":\"{}\",\"hash\":\"{}\",",
+            match node {
+                Node::Branch(_) => "branch",
+                Node::Extension(_) => "extension",
+                Node::Leaf(_) => "leaf",
+            },
+            nibble_list_to_hex(path),
+            bytes_to_hex(&hash),
+        )
+        .unwrap();
+
+        match node {
+            Node::Branch(branch_node) => {
+                json.push_str("\"value\":");
+                if branch_node.value_ref.is_valid() {
+                    let (_, value) = self
+                        .values
+                        .get(*branch_node.value_ref)
+                        .expect("inconsistent internal tree structure");
+                    write!(json, "\"{}\"", bytes_to_hex(&value.encoded())).unwrap();
+                } else {
+                    json.push_str("null");
+                }
+
+                json.push_str(",\"children\":{");
+                let choices = branch_node
+                    .choices
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, choice)| choice.is_valid());
+                for (position, (index, choice)) in choices.enumerate() {
+                    if position != 0 {
+                        json.push(',');
+                    }
+                    write!(json, "\"{index:x}\":").unwrap();
+
+                    path.push(index as u8);
+                    self.write_json_node(json, *choice, path);
+                    path.pop();
+                }
+                json.push('}');
+            }
+            Node::Extension(extension_node) => {
+                let prefix = extension_node
+                    .prefix
+                    .iter()
+                    .map(u8::from)
+                    .collect::<Vec<_>>();
+                write!(
+                    json,
+                    "\"prefix\":\"{}\",\"child\":",
+                    nibble_list_to_hex(&prefix)
+                )
+                .unwrap();
+
+                let path_len = path.len();
+                path.extend(prefix);
+                self.write_json_node(json, extension_node.child_ref, path);
+                path.truncate(path_len);
+            }
+            Node::Leaf(leaf_node) => {
+                let (key, value) = self
+                    .values
+                    .get(*leaf_node.value_ref)
+                    .expect("inconsistent internal tree structure");
+                write!(
+                    json,
+                    "\"key\":\"{}\",\"value\":\"{}\"",
+                    bytes_to_hex(key.as_ref()),
+                    bytes_to_hex(&value.encoded()),
+                )
+                .unwrap();
+            }
+        }
+
+        json.push('}');
+    }
+}
+
+/// Render a sequence of nibbles (one per byte) with one digit each.
+fn nibble_list_to_hex(nibbles: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + nibbles.len());
+    hex.push_str("0x");
+    for nibble in nibbles {
+        write!(hex, "{nibble:x}").unwrap();
+    }
+
+    hex
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + 2 * bytes.len());
+    hex.push_str("0x");
+    for byte in bytes {
+        write!(hex, "{byte:02x}").unwrap();
+    }
+
+    hex
+}
+
+#[cfg(test)]
+mod test {
+    use crate::PatriciaMerkleTree;
+    use sha3::Keccak256;
+
+    #[test]
+    fn to_json_empty() {
+        let tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.to_json(), "null");
+    }
+
+    #[test]
+    fn to_json() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(&[0x12, 0x34], &[0x01]);
+        tree.insert(&[0x12, 0x35], &[0x02]);
+        tree.insert(&[0x12], &[0x03]);
+
+        // An extension over a branch with a value, then a branch with two leaves. Only the root and
+        // the first branch are long enough to be hashed.
+        assert_eq!(
+            tree.to_json(),
+            concat!(
+                r#"{"type":"extension","path":"0x","#,
+                r#""hash":"0x98b1c530ab18f8d974cc5785939e170d86d9b4506e9331b4e60a7b7a2cea1b3b","#,
+                r#""prefix":"0x12","child":{"type":"branch","path":"0x12","#,
+                r#""hash":"0x1c58bb02a5af4814af4098c0892eabf5fea92e809f23e4034b0698fd5b1f1b9f","#,
+                r#""value":"0x03","children":{"3":{"type":"branch","path":"0x123","#,
+                r#""hash":"0xd580808080c22001c220028080808080808080808080","#,
+                r#""value":null,"children":{"#,
+                r#""4":{"type":"leaf","path":"0x1234","hash":"0xc22001","key":"0x1234","value":"0x01"},"#,
+                r#""5":{"type":"leaf","path":"0x1235","hash":"0xc22002","key":"0x1235","value":"0x02"}"#,
+                r#"}}}}}"#,
+            ),
+        );
+        assert!(tree
+            .to_json()
+            .contains(&format!("{:x}", tree.compute_hash())));
+    }
+}
@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+
+/// A value that can be stored in the tree.
+///
+/// Values are only serialized when computing hashes, which allows the tree to own structured values
+/// without keeping a separate encoded copy around.
+pub trait Encodable {
+    /// Append the value's encoding to `out`.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// Return the value's encoding.
+    fn encoded(&self) -> Cow<'_, [u8]> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        Cow::Owned(out)
+    }
+
+    /// Return the length of the value's encoding.
+    ///
+    /// By default it encodes the value, so types which can tell their length upfront should
+    /// override it.
+    fn encoded_len(&self) -> usize {
+        self.encoded().len()
+    }
+}
+
+impl<T> Encodable for T
+where
+    T: AsRef<[u8]>,
+{
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+
+    fn encoded(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_ref())
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.as_ref().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Pair(u8, u8);
+
+    impl Encodable for Pair {
+        fn encode_to(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&[self.0, self.1]);
+        }
+    }
+
+    #[test]
+    fn encoded_bytes_borrowed() {
+        let value = vec![1u8, 2, 3];
+        assert!(matches!(value.encoded(), Cow::Borrowed(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn encoded_custom_owned() {
+        let value = Pair(4, 5);
+        assert!(matches!(value.encoded(), Cow::Owned(x) if x == [4, 5]));
+
+        let mut out = vec![3];
+        value.encode_to(&mut out);
+        assert_eq!(out, [3, 4, 5]);
+    }
+
+    #[test]
+    fn encoded_len() {
+        assert_eq!(vec![1u8, 2, 3].encoded_len(), 3);
+        assert_eq!(Pair(4, 5).encoded_len(), 2);
+    }
+}
@@ -1,11 +1,37 @@
 use crate::nibble::{NibbleSlice, NibbleVec};
-use digest::{Digest, Output};
+use digest::{typenum::Unsigned, Digest, Output, OutputSizeUser};
 use std::{
     cell::{Cell, Ref, RefCell},
     cmp::min,
+    marker::PhantomData,
     mem::size_of,
 };
 
+/// Encodings shorter than this are inlined into their parents instead of being hashed.
+///
+/// It's fixed by the Ethereum encoding, and therefore independent of the digest's output size. The
+/// digest's output must be at least this long since it doubles as the inline buffer, which is
+/// checked by `OutputSizeCheck`.
+pub(crate) const INLINE_THRESHOLD: usize = 32;
+
+/// Compile-time check of a digest's output size.
+pub(crate) struct OutputSizeCheck<H>(PhantomData<H>);
+
+impl<H> OutputSizeCheck<H>
+where
+    H: Digest,
+{
+    /// Fails to evaluate, and therefore to compile wherever it's used, if the digest's output
+    /// can't hold the inline encodings.
+    pub(crate) const ASSERT: () = assert!(
+        <H as OutputSizeUser>::OutputSize::USIZE >= INLINE_THRESHOLD,
+        "the digest's output must be at least 32 bytes long",
+    );
+}
+
+/// Length marking a node hash as hashed (inline lengths are always below the threshold).
+const HASHED_LENGTH: usize = usize::MAX;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NodeHash<H>
 where
@@ -23,13 +49,23 @@ where
         self.length.set(0);
     }
 
-    pub fn extract_ref(&self) -> Option<NodeHashRef<H>> {
+    pub fn is_dirty(&self) -> bool {
+        self.length.get() == 0
+    }
+
+    /// Store an externally computed hash, marking it as clean.
+    pub fn preset(&self, hash: &Output<H>) {
+        self.hash_ref.borrow_mut().copy_from_slice(hash);
+        self.length.set(HASHED_LENGTH);
+    }
+
+    pub fn extract_ref(&self) -> Option<NodeHashRef<'_, H>> {
         let length = self.length.get();
         let hash_ref = self.hash_ref.borrow();
 
         match length {
             0 => None,
-            32 => Some(NodeHashRef::Hashed(hash_ref)),
+            HASHED_LENGTH => Some(NodeHashRef::Hashed(hash_ref)),
             l => Some(NodeHashRef::Inline(Ref::map(hash_ref, |x| &x[..l]))),
         }
     }
@@ -97,7 +133,7 @@ where
                     self.push_hash_update(&hash_ref[..self.parent.length.get()]);
                     self.hasher.take().unwrap().finalize_into(&mut hash_ref);
                 }
-                self.parent.length.set(32);
+                self.parent.length.set(HASHED_LENGTH);
                 NodeHashRef::Hashed(self.parent.hash_ref.borrow())
             }
             None => NodeHashRef::Inline(Ref::map(self.parent.hash_ref.borrow(), |x| {
@@ -118,7 +154,20 @@ where
         }
     }
 
-    pub fn write_path_vec(&mut self, value: &NibbleVec, kind: PathKind) {
+    fn push_hash_update(&mut self, data: &[u8]) {
+        let hasher = self.hasher.get_or_insert_with(H::new);
+        hasher.update(data);
+    }
+}
+
+/// Sink for RLP-encoded node data.
+///
+/// Nodes are encoded by streaming into an encoder, which is either a `NodeHasher` (to compute
+/// their hashes) or a byte buffer (to retrieve the encodings themselves).
+pub(crate) trait NodeEncoder {
+    fn write_raw(&mut self, value: &[u8]);
+
+    fn write_path_vec(&mut self, value: &NibbleVec, kind: PathKind) {
         let mut flag = kind.into_flag();
 
         // TODO: Do not use iterators.
@@ -142,7 +191,7 @@ where
         }
     }
 
-    pub fn write_path_slice(&mut self, value: &NibbleSlice, kind: PathKind) {
+    fn write_path_slice(&mut self, value: &NibbleSlice, kind: PathKind) {
         let mut flag = kind.into_flag();
 
         // TODO: Do not use iterators.
@@ -166,7 +215,7 @@ where
         }
     }
 
-    pub fn write_bytes(&mut self, value: &[u8]) {
+    fn write_bytes(&mut self, value: &[u8]) {
         if value.len() == 1 && value[0] < 128 {
             self.write_raw(&[value[0]]);
         } else {
@@ -175,7 +224,7 @@ where
         }
     }
 
-    pub fn write_list_header(&mut self, children_len: usize) {
+    fn write_list_header(&mut self, children_len: usize) {
         self.write_len(0xC0, 0xF7, children_len);
     }
 
@@ -189,14 +238,35 @@ where
             }
         }
     }
+}
+
+/// Encoder which only counts the encoded bytes.
+#[derive(Default)]
+pub(crate) struct EncodedLen(pub usize);
+
+impl NodeEncoder for EncodedLen {
+    fn write_raw(&mut self, value: &[u8]) {
+        self.0 += value.len();
+    }
+}
+
+impl NodeEncoder for Vec<u8> {
+    fn write_raw(&mut self, value: &[u8]) {
+        self.extend_from_slice(value);
+    }
+}
 
-    pub fn write_raw(&mut self, value: &[u8]) {
+impl<'a, H> NodeEncoder for NodeHasher<'a, H>
+where
+    H: 'a + Digest,
+{
+    fn write_raw(&mut self, value: &[u8]) {
         let mut length = self.parent.length.get();
         let mut hash_ref = self.parent.hash_ref.borrow_mut();
 
         let mut current_pos = 0;
         while current_pos < value.len() {
-            let copy_len = min(32 - length, value.len() - current_pos);
+            let copy_len = min(INLINE_THRESHOLD - length, value.len() - current_pos);
 
             let target_slice = &mut hash_ref[length..length + copy_len];
             let source_slice = &value[current_pos..current_pos + copy_len];
@@ -205,19 +275,14 @@ where
             current_pos += copy_len;
             length += copy_len;
 
-            if length == 32 {
-                self.push_hash_update(&hash_ref);
+            if length == INLINE_THRESHOLD {
+                self.push_hash_update(&hash_ref[..INLINE_THRESHOLD]);
                 length = 0;
             }
         }
 
         self.parent.length.set(length);
     }
-
-    fn push_hash_update(&mut self, data: &[u8]) {
-        let hasher = self.hasher.get_or_insert_with(H::new);
-        hasher.update(data);
-    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -239,3 +304,59 @@ fn compute_byte_usage(value: usize) -> usize {
     let bits_used = usize::BITS as usize - value.leading_zeros() as usize;
     (bits_used.saturating_sub(1) >> 3) + 1
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha3::Keccak256;
+
+    #[test]
+    fn write_bytes_length_boundaries() {
+        for (len, header) in [
+            (55, &[0xB7][..]),
+            (56, &[0xB8, 0x38]),
+            (255, &[0xB8, 0xFF]),
+            (256, &[0xB9, 0x01, 0x00]),
+            (65536, &[0xBA, 0x01, 0x00, 0x00]),
+        ] {
+            let value = vec![0xAA; len];
+
+            let mut encoding = Vec::new();
+            encoding.write_bytes(&value);
+
+            assert_eq!(&encoding[..header.len()], header);
+            assert_eq!(&encoding[header.len()..], value.as_slice());
+            assert_eq!(
+                NodeHasher::<Keccak256>::bytes_len(len, 0xAA),
+                encoding.len(),
+            );
+        }
+    }
+
+    #[test]
+    fn write_list_header_length_boundaries() {
+        for (len, header) in [
+            (55, &[0xF7][..]),
+            (56, &[0xF8, 0x38]),
+            (255, &[0xF8, 0xFF]),
+            (256, &[0xF9, 0x01, 0x00]),
+            (
+                usize::MAX,
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            ),
+        ] {
+            let mut encoding = Vec::new();
+            encoding.write_list_header(len);
+
+            assert_eq!(encoding, header);
+        }
+    }
+
+    #[test]
+    fn compute_byte_usage_bounds() {
+        assert_eq!(compute_byte_usage(0), 1);
+        assert_eq!(compute_byte_usage(0xFF), 1);
+        assert_eq!(compute_byte_usage(0x100), 2);
+        assert_eq!(compute_byte_usage(usize::MAX), size_of::<usize>());
+    }
+}
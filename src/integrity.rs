@@ -0,0 +1,162 @@
+use crate::{node::Node, Encodable, NodeRef, NodesStorage, RootMismatch};
+use digest::Digest;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// An advisory structural issue: the tree is still usable, but its shape isn't canonical.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StructuralIssue {
+    /// A branch without a value and with a single child (should have been an extension or merged
+    /// with its child).
+    SingleChildBranch(NodeRef),
+    /// A branch without children (should have been a leaf).
+    ChildlessBranch(NodeRef),
+    /// An extension with an empty prefix (should have been replaced by its child).
+    EmptyExtension(NodeRef),
+    /// An extension followed by another extension (both should have been merged).
+    MergeableExtension(NodeRef),
+    /// An extension followed by a leaf (should have been absorbed by the leaf).
+    ExtensionToLeaf(NodeRef),
+}
+
+/// Error returned when verifying a tree against a claimed root hash.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VerifyError {
+    /// The tree's shape isn't canonical, therefore its root hash can't be trusted.
+    StructuralIssues(Vec<StructuralIssue>),
+    /// The recomputed root hash is not the claimed one.
+    RootMismatch(RootMismatch),
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::StructuralIssues(issues) => {
+                write!(f, "{} structural issue(s) found", issues.len())
+            }
+            VerifyError::RootMismatch(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+pub(crate) fn collect_issues<P, V, H>(
+    nodes: &NodesStorage<P, V, H>,
+    node_ref: NodeRef,
+    issues: &mut Vec<StructuralIssue>,
+) where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    let node = nodes
+        .get(*node_ref)
+        .expect("inconsistent internal tree structure");
+
+    match node {
+        Node::Branch(branch_node) => {
+            match branch_node.occupied_mask().count_ones() {
+                0 => issues.push(StructuralIssue::ChildlessBranch(node_ref)),
+                1 if !branch_node.value_ref.is_valid() => {
+                    issues.push(StructuralIssue::SingleChildBranch(node_ref))
+                }
+                _ => {}
+            }
+
+            branch_node
+                .choices
+                .iter()
+                .filter(|choice| choice.is_valid())
+                .for_each(|choice| collect_issues(nodes, *choice, issues));
+        }
+        Node::Extension(extension_node) => {
+            if extension_node.prefix.is_empty() {
+                issues.push(StructuralIssue::EmptyExtension(node_ref));
+            }
+
+            match nodes.get(*extension_node.child_ref) {
+                Some(Node::Extension(_)) => {
+                    issues.push(StructuralIssue::MergeableExtension(node_ref))
+                }
+                Some(Node::Leaf(_)) => issues.push(StructuralIssue::ExtensionToLeaf(node_ref)),
+                _ => {}
+            }
+
+            collect_issues(nodes, extension_node.child_ref, issues);
+        }
+        Node::Leaf(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{pmt_node, pmt_state, pmt_tree};
+
+    #[test]
+    fn no_issues() {
+        let mut tree = pmt_tree!(Vec<u8>);
+
+        tree.insert(vec![0x00], vec![0x00]);
+        tree.insert(vec![0x01], vec![0x01]);
+        tree.insert(vec![0x01, 0x00], vec![0x01, 0x00]);
+        tree.insert(vec![0x10], vec![0x10]);
+
+        assert_eq!(tree.integrity_report(), []);
+    }
+
+    #[test]
+    fn single_child_branch() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                1 => branch {
+                    0 => leaf { vec![0x10] => vec![0x10] },
+                    2 => leaf { vec![0x12] => vec![0x12] },
+                },
+            }
+        };
+        let root_ref = NodeRef::new(nodes.insert(node.into()));
+
+        let mut issues = Vec::new();
+        collect_issues(&nodes, root_ref, &mut issues);
+        assert_eq!(issues, [StructuralIssue::SingleChildBranch(root_ref)]);
+    }
+
+    #[test]
+    fn extension_issues() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            extension { [1], extension { [2], branch {
+                0 => extension { [], leaf { vec![0x12, 0x00] => vec![0x00] } },
+                1 => leaf { vec![0x12, 0x10] => vec![0x10] },
+            } } }
+        };
+        let child_ref = node.child_ref;
+        let root_ref = NodeRef::new(nodes.insert(node.into()));
+
+        let mut issues = Vec::new();
+        collect_issues(&nodes, root_ref, &mut issues);
+
+        let inner_ref = match &nodes[*child_ref] {
+            Node::Extension(extension_node) => match &nodes[*extension_node.child_ref] {
+                Node::Branch(branch_node) => branch_node.choices[0],
+                _ => panic!("expected a branch node"),
+            },
+            _ => panic!("expected an extension node"),
+        };
+        assert_eq!(
+            issues,
+            [
+                StructuralIssue::MergeableExtension(root_ref),
+                StructuralIssue::EmptyExtension(inner_ref),
+                StructuralIssue::ExtensionToLeaf(inner_ref),
+            ],
+        );
+    }
+}
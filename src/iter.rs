@@ -0,0 +1,371 @@
+use crate::{node::Node, Encodable, NodeRef, NodesStorage, ValueRef, ValuesStorage};
+use digest::Digest;
+use std::vec;
+
+/// Iterator over the value references of a subtree, in path order.
+pub(crate) struct ValueRefs<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    nodes: &'a NodesStorage<P, V, H>,
+    stack: Vec<NodeRef>,
+}
+
+impl<'a, P, V, H> ValueRefs<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(nodes: &'a NodesStorage<P, V, H>, root_ref: NodeRef) -> Self {
+        Self {
+            nodes,
+            stack: if root_ref.is_valid() {
+                vec![root_ref]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl<'a, P, V, H> Iterator for ValueRefs<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = ValueRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node_ref) = self.stack.pop() {
+            let node = self
+                .nodes
+                .get(*node_ref)
+                .expect("inconsistent internal tree structure");
+
+            match node {
+                Node::Branch(branch_node) => {
+                    // Children are pushed in reverse so that the lowest choice is visited first. The
+                    // branch's value (if any) goes before all of them since its path is shorter.
+                    self.stack.extend(
+                        branch_node
+                            .choices
+                            .iter()
+                            .rev()
+                            .filter(|choice| choice.is_valid()),
+                    );
+
+                    if branch_node.value_ref.is_valid() {
+                        return Some(branch_node.value_ref);
+                    }
+                }
+                Node::Extension(extension_node) => self.stack.push(extension_node.child_ref),
+                Node::Leaf(leaf_node) => return Some(leaf_node.value_ref),
+            }
+        }
+
+        None
+    }
+}
+
+/// Iterator over the value references of a subtree, in reverse path order.
+pub(crate) struct ValueRefsRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    nodes: &'a NodesStorage<P, V, H>,
+    /// Nodes still to be visited (`Ok`), and branch values waiting for their children (`Err`).
+    stack: Vec<Result<NodeRef, ValueRef>>,
+}
+
+impl<'a, P, V, H> ValueRefsRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(nodes: &'a NodesStorage<P, V, H>, root_ref: NodeRef) -> Self {
+        Self {
+            nodes,
+            stack: if root_ref.is_valid() {
+                vec![Ok(root_ref)]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl<'a, P, V, H> Iterator for ValueRefsRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = ValueRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entry) = self.stack.pop() {
+            let node_ref = match entry {
+                Ok(node_ref) => node_ref,
+                Err(value_ref) => return Some(value_ref),
+            };
+
+            let node = self
+                .nodes
+                .get(*node_ref)
+                .expect("inconsistent internal tree structure");
+
+            match node {
+                Node::Branch(branch_node) => {
+                    // The branch's value (if any) goes after all of its children since its path is
+                    // shorter, therefore it's pushed first. Children are pushed in order so that the
+                    // highest choice is visited first.
+                    if branch_node.value_ref.is_valid() {
+                        self.stack.push(Err(branch_node.value_ref));
+                    }
+
+                    self.stack.extend(
+                        branch_node
+                            .choices
+                            .iter()
+                            .filter(|choice| choice.is_valid())
+                            .map(|choice| Ok(*choice)),
+                    );
+                }
+                Node::Extension(extension_node) => self.stack.push(Ok(extension_node.child_ref)),
+                Node::Leaf(leaf_node) => return Some(leaf_node.value_ref),
+            }
+        }
+
+        None
+    }
+}
+
+/// Iterator over the paths and values of a tree, sorted by path.
+pub struct Iter<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    values: &'a ValuesStorage<P, V>,
+    value_refs: ValueRefs<'a, P, V, H>,
+}
+
+impl<'a, P, V, H> Iter<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(
+        nodes: &'a NodesStorage<P, V, H>,
+        values: &'a ValuesStorage<P, V>,
+        root_ref: NodeRef,
+    ) -> Self {
+        Self {
+            values,
+            value_refs: ValueRefs::new(nodes, root_ref),
+        }
+    }
+}
+
+impl<'a, P, V, H> Iterator for Iter<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = (&'a P, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.value_refs.next().map(|value_ref| {
+            let (path, value) = self
+                .values
+                .get(*value_ref)
+                .expect("inconsistent internal tree structure");
+
+            (path, value)
+        })
+    }
+}
+
+/// Iterator over the paths and values of a tree, sorted by path in descending order.
+pub struct IterRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    values: &'a ValuesStorage<P, V>,
+    value_refs: ValueRefsRev<'a, P, V, H>,
+}
+
+impl<'a, P, V, H> IterRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(
+        nodes: &'a NodesStorage<P, V, H>,
+        values: &'a ValuesStorage<P, V>,
+        root_ref: NodeRef,
+    ) -> Self {
+        Self {
+            values,
+            value_refs: ValueRefsRev::new(nodes, root_ref),
+        }
+    }
+}
+
+impl<'a, P, V, H> Iterator for IterRev<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = (&'a P, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.value_refs.next().map(|value_ref| {
+            let (path, value) = self
+                .values
+                .get(*value_ref)
+                .expect("inconsistent internal tree structure");
+
+            (path, value)
+        })
+    }
+}
+
+/// Owning iterator over the paths and values of a tree, sorted by path.
+pub struct IntoIter<P, V> {
+    values: ValuesStorage<P, V>,
+    value_refs: vec::IntoIter<ValueRef>,
+}
+
+impl<P, V> IntoIter<P, V>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+{
+    pub(crate) fn new<H>(
+        nodes: &NodesStorage<P, V, H>,
+        values: ValuesStorage<P, V>,
+        root_ref: NodeRef,
+    ) -> Self
+    where
+        H: Digest,
+    {
+        Self {
+            values,
+            value_refs: ValueRefs::new(nodes, root_ref)
+                .collect::<Vec<_>>()
+                .into_iter(),
+        }
+    }
+}
+
+impl<P, V> Iterator for IntoIter<P, V> {
+    type Item = (P, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.value_refs
+            .next()
+            .map(|value_ref| self.values.remove(*value_ref))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.value_refs.size_hint()
+    }
+}
+
+impl<P, V> ExactSizeIterator for IntoIter<P, V> {}
+
+#[cfg(test)]
+mod test {
+    use crate::{node::Node, PatriciaMerkleTree};
+    use sha3::Keccak256;
+
+    #[test]
+    fn iter_branch_value_first() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for path in [
+            &[0x12, 0x34, 0xF0][..],
+            &[0x12, 0x34, 0x00],
+            &[0x12, 0x34],
+            &[0x12, 0x34, 0x50, 0x00],
+            &[0x12, 0x34, 0x50],
+        ] {
+            tree.insert(path, path);
+        }
+
+        // The root is an extension followed by a branch with a value, then its children.
+        let child_ref = match &tree.nodes[*tree.root_ref] {
+            Node::Extension(extension_node) => extension_node.child_ref,
+            _ => panic!("expected an extension node"),
+        };
+        assert!(matches!(
+            &tree.nodes[*child_ref],
+            Node::Branch(branch_node) if branch_node.value_ref.is_valid(),
+        ));
+
+        assert_eq!(
+            tree.iter().map(|(x, _)| *x).collect::<Vec<_>>(),
+            [
+                &[0x12, 0x34][..],
+                &[0x12, 0x34, 0x00],
+                &[0x12, 0x34, 0x50],
+                &[0x12, 0x34, 0x50, 0x00],
+                &[0x12, 0x34, 0xF0],
+            ],
+        );
+    }
+
+    #[test]
+    fn iter_rev_branch_value_last() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for path in [
+            &[0x12, 0x34, 0xF0][..],
+            &[0x12, 0x34, 0x00],
+            &[0x12, 0x34],
+            &[0x12, 0x34, 0x50, 0x00],
+            &[0x12, 0x34, 0x50],
+        ] {
+            tree.insert(path, path);
+        }
+
+        assert_eq!(
+            tree.iter_rev().map(|(x, _)| *x).collect::<Vec<_>>(),
+            [
+                &[0x12, 0x34, 0xF0][..],
+                &[0x12, 0x34, 0x50, 0x00],
+                &[0x12, 0x34, 0x50],
+                &[0x12, 0x34, 0x00],
+                &[0x12, 0x34],
+            ],
+        );
+    }
+
+    #[test]
+    fn iter_rev_reverses_iter() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        assert_eq!(tree.iter_rev().next(), None);
+
+        for i in 0..500u32 {
+            let path = i.to_be_bytes()[..(i % 4 + 1) as usize].to_vec();
+            tree.insert(path.clone(), path);
+        }
+
+        let mut forward = tree.iter().collect::<Vec<_>>();
+        forward.reverse();
+        assert_eq!(tree.iter_rev().collect::<Vec<_>>(), forward);
+    }
+}
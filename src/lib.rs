@@ -3,30 +3,58 @@
 #![deny(warnings)]
 
 use self::{
+    iter::ValueRefs,
     nibble::NibbleSlice,
-    node::{InsertAction, Node},
-    nodes::LeafNode,
-    storage::{NodeRef, NodesStorage, ValueRef, ValuesStorage},
+    storage::{NodesStorage, ValuesStorage},
 };
 use digest::{Digest, Output};
-use hashing::NodeHashRef;
+use hashing::{EncodedLen, NodeHashRef, OutputSizeCheck, INLINE_THRESHOLD};
 use slab::Slab;
-use std::mem::{replace, size_of};
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem::{replace, size_of, take},
+};
+
+pub use self::{
+    cursor::Cursor,
+    encodable::Encodable,
+    integrity::{StructuralIssue, VerifyError},
+    iter::{IntoIter, Iter, IterRev},
+    metadata::MetadataStorage,
+    nibble::{
+        hex_to_nibbles, max_depth_for_keylen, nibble_len, nibbles_to_hex, nibbles_to_hex_prefix,
+    },
+    node::{InsertAction, Node},
+    nodes::{BranchNode, ExtensionNode, LeafNode},
+    root_hash::{ParseRootHashError, RootHash, RootMismatch},
+    staging::StagingBuffer,
+    storage::{NodeBudgetExceeded, NodeRef, ValueRef},
+    view::RootView,
+};
 
+mod closest;
+mod cursor;
 #[cfg(feature = "tree-dump")]
 pub mod dump;
+mod encodable;
 mod hashing;
+mod integrity;
+mod iter;
+mod metadata;
 mod nibble;
 mod node;
 mod nodes;
+mod root_hash;
+mod staging;
 mod storage;
+mod view;
 
 /// Patricia Merkle Tree implementation.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct PatriciaMerkleTree<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     /// Reference to the root node.
@@ -36,184 +64,3081 @@ where
     nodes: NodesStorage<P, V, H>,
     /// Stores the actual nodes' hashed paths and values.
     values: ValuesStorage<P, V>,
+    /// Maps the digest of each value to the references storing it, if enabled.
+    value_index: Option<HashMap<Output<H>, Vec<ValueRef>>>,
 
     hash: (bool, Output<H>),
 }
 
+/// The kind of node a value is stored in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TerminalKind {
+    /// The value is stored in a leaf.
+    Leaf,
+    /// The value is stored in a branch, since its path is a prefix of other paths.
+    BranchValue,
+}
+
+/// The reason a path is not present in a tree, along with the nibble depth at which its walk stopped.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Absence {
+    /// The tree is empty.
+    EmptyTree,
+    /// The path ends at a branch without a value.
+    MissingBranchValue { at_depth: usize },
+    /// The path continues into a branch's choice that has no child.
+    EmptyBranchSlot { at_depth: usize, nibble: u8 },
+    /// The path doesn't match an extension's prefix.
+    ExtensionMismatch { at_depth: usize },
+    /// The path reaches a leaf holding another path.
+    LeafPathMismatch { at_depth: usize },
+}
+
+/// Patricia Merkle tree with owned byte paths.
+pub type ByteTrie<V, H> = PatriciaMerkleTree<Vec<u8>, V, H>;
+
 impl<P, V, H> PatriciaMerkleTree<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     /// Create an empty tree.
+    ///
+    /// The digest's output must be at least 32 bytes long, since it also holds the encodings of the
+    /// nodes inlined into their parents. Shorter digests are rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// use patricia_merkle_tree::PatriciaMerkleTree;
+    /// use sha3::Sha3_224;
+    ///
+    /// let tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Sha3_224>::new();
+    /// ```
     pub fn new() -> Self {
+        let () = OutputSizeCheck::<H>::ASSERT;
+
         Self {
             root_ref: NodeRef::default(),
             nodes: Slab::new(),
             values: Slab::new(),
+            value_index: None,
             hash: (false, Default::default()),
         }
     }
 
+    /// Create an empty tree with room for about `capacity` entries before reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut tree = Self::new();
+        tree.reserve(capacity);
+        tree
+    }
+
+    /// Reserve room for about `additional` more entries before reallocating.
+    ///
+    /// The nodes' arena is sized assuming about 1.5 nodes per entry, to account for the branches
+    /// (and their extensions) needed to tell the paths apart.
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional + additional / 2);
+        self.values.reserve(additional);
+    }
+
     /// Return whether the tree is empty.
     pub fn is_empty(&self) -> bool {
         self.nodes.is_empty()
     }
 
-    /// Return the number of values in the tree.
-    pub fn len(&self) -> usize {
-        self.values.len()
+    /// Return the number of values in the tree.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return an iterator over the paths and values, sorted by path.
+    pub fn iter(&self) -> Iter<'_, P, V, H> {
+        Iter::new(&self.nodes, &self.values, self.root_ref)
+    }
+
+    /// Return an iterator over the paths and values, sorted by path in descending order.
+    pub fn iter_rev(&self) -> IterRev<'_, P, V, H> {
+        IterRev::new(&self.nodes, &self.values, self.root_ref)
+    }
+
+    /// Return an unpositioned cursor over the paths and values, sorted by path.
+    pub fn cursor(&self) -> Cursor<'_, P, V, H> {
+        Cursor::new(&self.nodes, &self.values, self.root_ref)
+    }
+
+    /// Copy the paths and values into a sorted map.
+    pub fn to_btreemap(&self) -> BTreeMap<Vec<u8>, V>
+    where
+        V: Clone,
+    {
+        self.iter()
+            .map(|(path, value)| (path.as_ref().to_vec(), value.clone()))
+            .collect()
+    }
+
+    /// Copy the paths and values into a vector sorted by path, suitable for binary searches.
+    pub fn to_sorted_vec(&self) -> Vec<(Vec<u8>, V)>
+    where
+        V: Clone,
+    {
+        self.iter()
+            .map(|(path, value)| (path.as_ref().to_vec(), value.clone()))
+            .collect()
+    }
+
+    /// Fold over the paths and values, sorted by path.
+    ///
+    /// Paths are stored in full, so they are passed as is rather than rebuilt from the nodes.
+    pub fn fold<B>(&self, init: B, mut f: impl FnMut(B, &[u8], &V) -> B) -> B {
+        ValueRefs::new(&self.nodes, self.root_ref).fold(init, |acc, value_ref| {
+            let (path, value) = self
+                .values
+                .get(*value_ref)
+                .expect("inconsistent internal tree structure");
+
+            f(acc, path.as_ref(), value)
+        })
+    }
+
+    /// Return an iterator over the stored values in storage order, which is unrelated to their
+    /// paths' order.
+    ///
+    /// It's faster than `iter()` since it doesn't walk the nodes. There are no orphaned values since
+    /// replaced values are overwritten in place, and removed ones are freed.
+    pub fn raw_values(&self) -> impl '_ + Iterator<Item = &V> {
+        self.values.iter().map(|(_, (_, value))| value)
+    }
+
+    /// Return an iterator over the values stored in branches (those whose paths are prefixes of
+    /// other paths), in storage order.
+    pub fn branch_values(&self) -> impl '_ + Iterator<Item = (Vec<u8>, &V)> {
+        self.nodes.iter().filter_map(|(_, node)| match node {
+            Node::Branch(branch_node) if branch_node.value_ref.is_valid() => {
+                let (path, value) = self
+                    .values
+                    .get(*branch_node.value_ref)
+                    .expect("inconsistent internal tree structure");
+                Some((path.as_ref().to_vec(), value))
+            }
+            _ => None,
+        })
+    }
+
+    /// Retrieve a value from the tree given its path.
+    pub fn get(&self, path: &P) -> Option<&V> {
+        self.get_bytes(path.as_ref())
+    }
+
+    /// Retrieve a value from the tree given its path as a byte slice.
+    pub fn get_bytes(&self, path: &[u8]) -> Option<&V> {
+        self.nodes
+            .get(*self.root_ref)
+            .and_then(|root_node| root_node.get(&self.nodes, &self.values, NibbleSlice::new(path)))
+    }
+
+    /// Retrieve a value from the tree given a fixed-length path.
+    ///
+    /// Equivalent to `get_bytes`, but faster: extensions are skipped without comparing their
+    /// prefixes, and the full path is compared once against the stored one instead.
+    pub fn get_fixed<const N: usize>(&self, path: &[u8; N]) -> Option<&V> {
+        let mut offset = 0;
+        let mut node_ref = self.root_ref;
+        let value_ref = loop {
+            match self.nodes.get(*node_ref)? {
+                Node::Branch(branch_node) => {
+                    if offset == 2 * N {
+                        break branch_node.value_ref;
+                    }
+
+                    let byte = path[offset >> 1];
+                    let choice = if offset & 0x01 != 0 {
+                        byte & 0x0F
+                    } else {
+                        byte >> 4
+                    };
+
+                    offset += 1;
+                    node_ref = branch_node.choices[choice as usize];
+                }
+                Node::Extension(extension_node) => {
+                    offset += extension_node.prefix.len();
+                    if offset > 2 * N {
+                        return None;
+                    }
+
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => break leaf_node.value_ref,
+            }
+        };
+
+        let (value_path, value) = self.values.get(*value_ref)?;
+        (value_path.as_ref() == path).then_some(value)
+    }
+
+    /// Retrieve a value from the tree given its path as a string.
+    pub fn get_str(&self, path: &str) -> Option<&V> {
+        self.get_bytes(path.as_bytes())
+    }
+
+    /// Retrieve the entry whose path shares the longest nibble prefix with the given one.
+    ///
+    /// Ties are broken towards the closest diverging nibble, then towards the smaller one. See
+    /// the test cases for examples.
+    pub fn get_closest(&self, path: &[u8]) -> Option<(&P, &V)> {
+        closest::find_closest(&self.nodes, self.root_ref, NibbleSlice::new(path)).map(|value_ref| {
+            let (path, value) = self
+                .values
+                .get(*value_ref)
+                .expect("inconsistent internal tree structure");
+
+            (path, value)
+        })
+    }
+
+    /// Return the length of a value's encoding given its path.
+    pub fn value_len(&self, path: &P) -> Option<usize> {
+        self.get(path).map(Encodable::encoded_len)
+    }
+
+    /// Retrieve the metadata of a path from a sidecar storage.
+    pub fn get_meta<'a, M>(&self, metadata: &'a MetadataStorage<M>, path: &P) -> Option<&'a M> {
+        metadata.get(self.find_value_ref(path.as_ref())?)
+    }
+
+    /// Set the metadata of a path in a sidecar storage, returning the previous one.
+    ///
+    /// Nothing is set if the path is not present.
+    pub fn set_meta<M>(&self, metadata: &mut MetadataStorage<M>, path: &P, meta: M) -> Option<M> {
+        metadata.set(self.find_value_ref(path.as_ref())?, meta)
+    }
+
+    /// Return the digest of a value's bytes (without the leaf's encoding) given its path.
+    pub fn hash_of_value(&self, path: &P) -> Option<RootHash<H>> {
+        self.get(path)
+            .map(|value| H::digest(value.encoded()).into())
+    }
+
+    /// Start maintaining an index from values to their paths, used by `keys_for_value()`.
+    ///
+    /// The index is built from the current values and kept up to date by later modifications.
+    pub fn enable_value_index(&mut self) {
+        let mut value_index = HashMap::<_, Vec<_>>::new();
+        for (index, (_, value)) in &self.values {
+            value_index
+                .entry(H::digest(value.encoded()))
+                .or_default()
+                .push(ValueRef::new(index));
+        }
+
+        self.value_index = Some(value_index);
+    }
+
+    /// Return the paths (sorted) whose value's encoding is `value`.
+    ///
+    /// Without the value index enabled, every value is checked.
+    pub fn keys_for_value(&self, value: &[u8]) -> Vec<Vec<u8>> {
+        match &self.value_index {
+            Some(value_index) => {
+                let mut paths = value_index
+                    .get(&H::digest(value))
+                    .into_iter()
+                    .flatten()
+                    .map(|value_ref| {
+                        self.values
+                            .get(**value_ref)
+                            .expect("inconsistent internal tree structure")
+                    })
+                    .filter(|(_, x)| x.encoded() == value)
+                    .map(|(path, _)| path.as_ref().to_vec())
+                    .collect::<Vec<_>>();
+
+                paths.sort();
+                paths
+            }
+            None => self
+                .iter()
+                .filter(|(_, x)| x.encoded() == value)
+                .map(|(path, _)| path.as_ref().to_vec())
+                .collect(),
+        }
+    }
+
+    /// Retrieve a value from the tree along with the kind of node storing it.
+    pub fn get_kind(&self, path: &P) -> Option<(TerminalKind, &V)> {
+        let (kind, value_ref) = match self.find_terminal(path.as_ref())? {
+            Node::Branch(branch_node) => (TerminalKind::BranchValue, branch_node.value_ref),
+            Node::Leaf(leaf_node) => (TerminalKind::Leaf, leaf_node.value_ref),
+            Node::Extension(_) => unreachable!(),
+        };
+
+        let (_, value) = self
+            .values
+            .get(*value_ref)
+            .expect("inconsistent internal tree structure");
+        Some((kind, value))
+    }
+
+    /// Retrieve a copy of the node storing a path's value (either a leaf or a branch).
+    pub fn get_terminal_node(&self, path: &P) -> Option<Node<P, V, H>>
+    where
+        P: Clone,
+        V: Clone,
+        H: Clone,
+    {
+        self.find_terminal(path.as_ref()).cloned()
+    }
+
+    /// Find the reference to a path's value.
+    fn find_value_ref(&self, path: &[u8]) -> Option<ValueRef> {
+        self.find_terminal(path).map(|node| match node {
+            Node::Branch(branch_node) => branch_node.value_ref,
+            Node::Leaf(leaf_node) => leaf_node.value_ref,
+            Node::Extension(_) => unreachable!(),
+        })
+    }
+
+    /// Find the node storing a path's value.
+    fn find_terminal(&self, path: &[u8]) -> Option<&Node<P, V, H>> {
+        let mut path = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        loop {
+            let node = self.nodes.get(*node_ref)?;
+            match node {
+                Node::Branch(branch_node) => match path.next() {
+                    Some(choice) => node_ref = branch_node.choices[choice as usize],
+                    None => return branch_node.value_ref.is_valid().then_some(node),
+                },
+                Node::Extension(extension_node) => {
+                    if !path.skip_prefix(&extension_node.prefix) {
+                        return None;
+                    }
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => {
+                    return leaf_node.get(&self.nodes, &self.values, path).map(|_| node)
+                }
+            }
+        }
+    }
+
+    /// Explain why a path is not present in the tree, or return `None` if it is.
+    pub fn explain_absence(&self, path: &P) -> Option<Absence> {
+        let mut path = NibbleSlice::new(path.as_ref());
+        let mut node_ref = self.root_ref;
+        loop {
+            let at_depth = path.offset();
+            let node = match self.nodes.get(*node_ref) {
+                Some(x) => x,
+                None => return Some(Absence::EmptyTree),
+            };
+
+            match node {
+                Node::Branch(branch_node) => match path.next() {
+                    Some(nibble) => {
+                        node_ref = branch_node.choices[nibble as usize];
+                        if !node_ref.is_valid() {
+                            return Some(Absence::EmptyBranchSlot {
+                                at_depth,
+                                nibble: nibble as u8,
+                            });
+                        }
+                    }
+                    None => {
+                        return (!branch_node.value_ref.is_valid())
+                            .then_some(Absence::MissingBranchValue { at_depth })
+                    }
+                },
+                Node::Extension(extension_node) => {
+                    if !path.skip_prefix(&extension_node.prefix) {
+                        return Some(Absence::ExtensionMismatch { at_depth });
+                    }
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => {
+                    return leaf_node
+                        .get(&self.nodes, &self.values, path)
+                        .is_none()
+                        .then_some(Absence::LeafPathMismatch { at_depth })
+                }
+            }
+        }
+    }
+
+    /// Retrieve a stored path and its value directly from a value reference.
+    pub fn value_by_ref(&self, value_ref: ValueRef) -> Option<(&[u8], &V)> {
+        self.values
+            .get(*value_ref)
+            .map(|(path, value)| (path.as_ref(), value))
+    }
+
+    /// Insert a value into the tree.
+    ///
+    /// If the stored value's encoding is identical to the new one's, the tree (including its cached
+    /// hashes) is left untouched and the new value is returned back. Both values are
+    /// interchangeable as far as the tree is concerned, since it only ever uses their encodings.
+    pub fn insert(&mut self, path: P, value: V) -> Option<V> {
+        self.insert_value(path, value).1
+    }
+
+    /// Remove a value from the tree given its path, returning it if it was present.
+    ///
+    /// Nodes left with a single child are collapsed, so the tree has the same structure (and root
+    /// hash) as one built from the remaining values only. The value's slot may be reused by later
+    /// insertions, therefore any metadata kept for it in a `MetadataStorage` should be cleared.
+    pub fn remove(&mut self, path: &P) -> Option<V> {
+        self.remove_path(path.as_ref())
+    }
+
+    fn remove_path(&mut self, path: &[u8]) -> Option<V> {
+        let root_node = self.nodes.try_remove(*self.root_ref)?;
+        let (root_node, value_ref) =
+            root_node.remove(&mut self.nodes, &self.values, NibbleSlice::new(path));
+        self.root_ref = match root_node {
+            Some(root_node) => NodeRef::new(self.nodes.insert(root_node)),
+            None => NodeRef::default(),
+        };
+
+        let value_ref = value_ref?;
+        self.hash.0 = false;
+
+        let (_, value) = self.values.remove(*value_ref);
+        self.unindex_value(value_ref, &value);
+
+        Some(value)
+    }
+
+    /// Remove the values of many paths from the tree, returning how many of them were present.
+    ///
+    /// The root hash is only recomputed once, by the next `compute_hash()`.
+    pub fn remove_batch(&mut self, paths: impl IntoIterator<Item = P>) -> usize {
+        paths
+            .into_iter()
+            .filter(|path| self.remove(path).is_some())
+            .count()
+    }
+
+    /// Insert a value into the tree only if its path is not present, returning whether it was
+    /// inserted.
+    pub fn insert_if_absent(&mut self, path: P, value: V) -> bool {
+        self.insert_value_with(path, value, false).1.is_none()
+    }
+
+    /// Insert a value into the tree along with the precomputed hash of its leaf.
+    ///
+    /// The hash must be the leaf's at the position it's inserted into, which is trusted (and only
+    /// verified in debug builds). It's discarded if the value ends up in a branch, or once later
+    /// insertions move the leaf.
+    pub fn insert_with_hash(&mut self, path: P, value: V, leaf_hash: &Output<H>) -> Option<V> {
+        let (value_ref, old_value) = self.insert_value(path, value);
+
+        let (path, _) = self
+            .values
+            .get(*value_ref)
+            .expect("inconsistent internal tree structure");
+        if let Some((leaf_node, key_offset)) = self.find_leaf(path.as_ref()) {
+            leaf_node.preset_hash(&self.nodes, &self.values, key_offset, leaf_hash);
+        }
+
+        old_value
+    }
+
+    /// Insert a value into the tree and set its metadata in a sidecar storage.
+    ///
+    /// The metadata is set even if the value is left untouched.
+    pub fn insert_with_meta<M>(
+        &mut self,
+        metadata: &mut MetadataStorage<M>,
+        path: P,
+        value: V,
+        meta: M,
+    ) -> Option<V> {
+        let (value_ref, old_value) = self.insert_value(path, value);
+        metadata.set(value_ref, meta);
+        old_value
+    }
+
+    /// Replace the value of a path present in the tree, returning the old one.
+    ///
+    /// Unlike `insert()`, nothing is done if the path is not present.
+    pub fn swap_value(&mut self, path: &P, value: V) -> Option<V> {
+        self.swap_path_value(path.as_ref(), value)
+    }
+
+    fn swap_path_value(&mut self, path: &[u8], value: V) -> Option<V> {
+        let value_ref = self.find_value_ref(path)?;
+
+        // Mark hash as dirty, along with the nodes' on the way to the value.
+        self.hash.0 = false;
+        self.mark_path_dirty(path);
+
+        let value_hash = self
+            .value_index
+            .is_some()
+            .then(|| H::digest(value.encoded()));
+        let (_, old_value) = self
+            .values
+            .get_mut(*value_ref)
+            .expect("inconsistent internal tree structure");
+        let old_value = replace(old_value, value);
+
+        self.reindex_value(value_ref, Some(&old_value), value_hash);
+        Some(old_value)
+    }
+
+    /// Mark the hashes of the nodes on the way to a path as dirty.
+    fn mark_path_dirty(&mut self, path: &[u8]) {
+        let mut path = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        while let Some(node) = self.nodes.get_mut(*node_ref) {
+            match node {
+                Node::Branch(branch_node) => {
+                    branch_node.hash.mark_as_dirty();
+                    match path.next() {
+                        Some(choice) => node_ref = branch_node.choices[choice as usize],
+                        None => break,
+                    }
+                }
+                Node::Extension(extension_node) => {
+                    extension_node.hash.mark_as_dirty();
+                    if !path.skip_prefix(&extension_node.prefix) {
+                        break;
+                    }
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => {
+                    leaf_node.hash.mark_as_dirty();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Insert a value into the tree and check the resulting root hash against an expected one.
+    ///
+    /// The value is inserted even if the root hashes don't match.
+    pub fn insert_checked(
+        &mut self,
+        path: P,
+        value: V,
+        expected_root_after: &[u8],
+    ) -> Result<(), RootMismatch> {
+        self.insert(path, value);
+
+        let root_hash = self.compute_hash();
+        if root_hash.as_slice() == expected_root_after {
+            Ok(())
+        } else {
+            Err(RootMismatch {
+                expected: expected_root_after.to_vec(),
+                actual: root_hash.as_slice().to_vec(),
+            })
+        }
+    }
+
+    /// Insert a value into the tree unless it would make the tree have more than `max_nodes` nodes,
+    /// in which case the tree is left unchanged.
+    pub fn insert_bounded(
+        &mut self,
+        path: P,
+        value: V,
+        max_nodes: usize,
+    ) -> Result<Option<V>, NodeBudgetExceeded> {
+        self.insert_value_bounded(path, value, max_nodes)
+            .map(|(_, old_value)| old_value)
+    }
+
+    /// Insert a batch of values with a node budget (as in `insert_bounded`), either all of them or
+    /// none.
+    ///
+    /// On failure, the index of the entry which went over the budget is returned along with the
+    /// error, and the tree is left with the same values (and root hash) as before. The entries
+    /// already inserted are undone one by one, so a failure costs as much as the inserted part of
+    /// the batch.
+    pub fn try_insert_batch(
+        &mut self,
+        entries: impl IntoIterator<Item = (P, V)>,
+        max_nodes: usize,
+    ) -> Result<(), (usize, NodeBudgetExceeded)> {
+        // The value references of the inserted entries, along with the values they replaced.
+        let mut undo_log = Vec::new();
+        for (index, (path, value)) in entries.into_iter().enumerate() {
+            match self.insert_value_bounded(path, value, max_nodes) {
+                Ok(entry) => undo_log.push(entry),
+                Err(e) => {
+                    // Undone in reverse, so that entries repeating a path restore its first value.
+                    for (value_ref, old_value) in undo_log.into_iter().rev() {
+                        let path = self
+                            .values
+                            .get(*value_ref)
+                            .expect("inconsistent internal tree structure")
+                            .0
+                            .as_ref()
+                            .to_vec();
+                        match old_value {
+                            Some(old_value) => self.swap_path_value(&path, old_value),
+                            None => self.remove_path(&path),
+                        };
+                    }
+
+                    return Err((index, e));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_value_bounded(
+        &mut self,
+        path: P,
+        value: V,
+        max_nodes: usize,
+    ) -> Result<(ValueRef, Option<V>), NodeBudgetExceeded> {
+        let new_nodes = self.count_new_nodes(path.as_ref());
+        let required = self.nodes.len() + new_nodes;
+        if required > max_nodes {
+            return Err(NodeBudgetExceeded {
+                max_nodes,
+                required,
+            });
+        }
+
+        let entry = self.insert_value(path, value);
+        debug_assert_eq!(self.nodes.len(), required, "wrong node count estimate");
+
+        Ok(entry)
+    }
+
+    /// Count the nodes an insertion would add to the tree, without modifying it.
+    ///
+    /// It mirrors the nodes' insertion logic: a single node is split (if any), and only the nodes
+    /// created by the split count since the split node's slot is reused.
+    fn count_new_nodes(&self, path: &[u8]) -> usize {
+        let mut path = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        loop {
+            let node = match self.nodes.get(*node_ref) {
+                Some(node) => node,
+                None => return 1,
+            };
+
+            match node {
+                Node::Branch(branch_node) => match path.next() {
+                    Some(choice) if branch_node.choices[choice as usize].is_valid() => {
+                        node_ref = branch_node.choices[choice as usize];
+                    }
+                    Some(_) => return 1,
+                    None => return 0,
+                },
+                Node::Extension(extension_node) => {
+                    if path.clone().skip_prefix(&extension_node.prefix) {
+                        path.skip_prefix(&extension_node.prefix);
+                        node_ref = extension_node.child_ref;
+                        continue;
+                    }
+
+                    // The extension is replaced by its left part (if any) followed by a branch,
+                    // then its right part (if any). The branch gets a new leaf unless the path ends
+                    // there.
+                    let offset = path.clone().count_prefix_vec(&extension_node.prefix);
+                    path.offset_add(offset);
+
+                    return (offset != 0) as usize
+                        + (extension_node.prefix.len() - offset > 1) as usize
+                        + (path.len() != 0) as usize;
+                }
+                Node::Leaf(leaf_node) => {
+                    let (value_path, _) = self
+                        .values
+                        .get(*leaf_node.value_ref)
+                        .expect("inconsistent internal tree structure");
+                    if path.cmp_rest(value_path.as_ref()) {
+                        return 0;
+                    }
+
+                    // The leaf is replaced by an extension (if there's a shared prefix) followed by
+                    // a branch, which holds the new leaf or the new value, and the old leaf or the
+                    // old value.
+                    let offset = path.count_prefix_slice(&NibbleSlice::new_offset(
+                        value_path.as_ref(),
+                        path.offset(),
+                    ));
+                    let absolute_offset = path.offset() + offset;
+                    let branch_values = (absolute_offset == 2 * path.as_ref().len()) as usize
+                        + (absolute_offset == 2 * value_path.as_ref().len()) as usize;
+
+                    return (offset != 0) as usize + 2 - branch_values;
+                }
+            }
+        }
+    }
+
+    fn insert_value(&mut self, path: P, value: V) -> (ValueRef, Option<V>) {
+        self.insert_value_with(path, value, true)
+    }
+
+    /// Insert a value, or hand it back if its path is already present and either `overwrite` is
+    /// false or the stored value's encoding is identical.
+    fn insert_value_with(&mut self, path: P, value: V, overwrite: bool) -> (ValueRef, Option<V>) {
+        let (value_ref, old_value) = match self.nodes.try_remove(*self.root_ref) {
+            Some(root_node) => {
+                // If the tree is not empty, call the root node's insertion logic.
+                let (root_node, insert_action) = root_node.insert(
+                    &mut self.nodes,
+                    &mut self.values,
+                    NibbleSlice::new(path.as_ref()),
+                );
+                self.root_ref = NodeRef::new(self.nodes.insert(root_node));
+
+                match insert_action.quantize_self(self.root_ref) {
+                    InsertAction::Insert(node_ref) => {
+                        let value_ref = ValueRef::new(self.values.insert((path, value)));
+                        match self
+                            .nodes
+                            .get_mut(*node_ref)
+                            .expect("inconsistent internal tree structure")
+                        {
+                            Node::Leaf(leaf_node) => leaf_node.update_value_ref(value_ref),
+                            Node::Branch(branch_node) => branch_node.update_value_ref(value_ref),
+                            _ => panic!("inconsistent internal tree structure"),
+                        };
+
+                        (value_ref, None)
+                    }
+                    InsertAction::Replace(value_ref) => {
+                        let (_, old_value) = self
+                            .values
+                            .get_mut(*value_ref)
+                            .expect("inconsistent internal tree structure");
+
+                        // The nodes haven't been marked as dirty on the way down, so nothing has to
+                        // be rehashed if the value is kept or its encoding doesn't change.
+                        if !overwrite || old_value.encoded() == value.encoded() {
+                            return (value_ref, Some(value));
+                        }
+
+                        let old_value = replace(old_value, value);
+                        self.mark_path_dirty(path.as_ref());
+
+                        (value_ref, Some(old_value))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            None => {
+                // If the tree is empty, just add a leaf.
+                let value_ref = ValueRef::new(self.values.insert((path, value)));
+                self.root_ref = NodeRef::new(self.nodes.insert(LeafNode::new(value_ref).into()));
+
+                (value_ref, None)
+            }
+        };
+
+        // Mark hash as dirty.
+        self.hash.0 = false;
+
+        let value_hash = self
+            .value_index
+            .is_some()
+            .then(|| H::digest(self.values[*value_ref].1.encoded()));
+        self.reindex_value(value_ref, old_value.as_ref(), value_hash);
+        (value_ref, old_value)
+    }
+
+    /// Update the value index (if enabled) after a value reference's value has been replaced by
+    /// one with the given digest.
+    fn reindex_value(
+        &mut self,
+        value_ref: ValueRef,
+        old_value: Option<&V>,
+        value_hash: Option<Output<H>>,
+    ) {
+        if let Some(old_value) = old_value {
+            self.unindex_value(value_ref, old_value);
+        }
+
+        if let (Some(value_index), Some(value_hash)) = (&mut self.value_index, value_hash) {
+            value_index.entry(value_hash).or_default().push(value_ref);
+        }
+    }
+
+    /// Remove a value reference from the value index (if enabled).
+    fn unindex_value(&mut self, value_ref: ValueRef, value: &V) {
+        let Some(value_index) = &mut self.value_index else {
+            return;
+        };
+
+        let value_hash = H::digest(value.encoded());
+        if let Some(value_refs) = value_index.get_mut(&value_hash) {
+            value_refs.retain(|x| *x != value_ref);
+            if value_refs.is_empty() {
+                value_index.remove(&value_hash);
+            }
+        }
+    }
+
+    /// Find the leaf holding a path's value along with its nibble offset within the tree.
+    fn find_leaf(&self, path: &[u8]) -> Option<(&LeafNode<P, V, H>, usize)> {
+        let mut path = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        loop {
+            match self.nodes.get(*node_ref)? {
+                Node::Branch(branch_node) => {
+                    node_ref = branch_node.choices[path.next()? as usize];
+                }
+                Node::Extension(extension_node) => {
+                    if !path.skip_prefix(&extension_node.prefix) {
+                        return None;
+                    }
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(leaf_node) => return Some((leaf_node, path.offset())),
+            }
+        }
+    }
+
+    /// Apply the insertions collected in a staging buffer, in path order.
+    pub fn apply_staged(&mut self, staged: StagingBuffer<P, V>) {
+        for (path, value) in staged.into_sorted() {
+            self.insert(path, value);
+        }
+    }
+
+    /// Append entries whose paths are ascending and bigger than every path in the tree.
+    ///
+    /// Only the nodes on the way to the new entries are modified, so the cached hashes of the rest of
+    /// the tree are kept. The order is only checked in debug builds.
+    pub fn extend_sorted(&mut self, entries: impl IntoIterator<Item = (P, V)>) {
+        #[cfg(debug_assertions)]
+        let mut last_path = self.cursor().prev().map(|(path, _)| path.to_vec());
+
+        for (path, value) in entries {
+            #[cfg(debug_assertions)]
+            {
+                assert!(
+                    last_path.as_deref() < Some(path.as_ref()),
+                    "paths are not ascending"
+                );
+                last_path = Some(path.as_ref().to_vec());
+            }
+
+            self.insert(path, value);
+        }
+    }
+
+    /// Move the entries whose paths are not smaller than `path` into a new tree and return it.
+    ///
+    /// Both halves are rebuilt from their entries, therefore every cached hash is lost. The new tree
+    /// has a value index if this one has.
+    pub fn split_off(&mut self, path: &[u8]) -> Self {
+        let value_index = self.value_index.is_some();
+        let entries = take(self).into_iter();
+
+        let mut other = Self::new();
+        if value_index {
+            self.enable_value_index();
+            other.enable_value_index();
+        }
+
+        let mut entries = entries.peekable();
+        self.extend_sorted(std::iter::from_fn(|| {
+            entries.next_if(|(entry_path, _)| entry_path.as_ref() < path)
+        }));
+        other.extend_sorted(entries);
+
+        other
+    }
+
+    /// Convert the tree to use another digest, keeping its structure and values.
+    ///
+    /// Every hash is recomputed under the new digest when needed.
+    pub fn rehash_into<H2>(self) -> PatriciaMerkleTree<P, V, H2>
+    where
+        H2: Digest,
+    {
+        let () = OutputSizeCheck::<H2>::ASSERT;
+
+        let mut tree = PatriciaMerkleTree {
+            root_ref: self.root_ref,
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|(index, node)| (index, node.convert()))
+                .collect(),
+            values: self.values,
+            value_index: None,
+            hash: (false, Default::default()),
+        };
+
+        if self.value_index.is_some() {
+            tree.enable_value_index();
+        }
+        tree
+    }
+
+    /// Build a tree with the same paths and structure, but with the values transformed by `f`.
+    ///
+    /// Every hash is recomputed for the new values when needed.
+    pub fn map_values<V2, F>(&self, f: F) -> PatriciaMerkleTree<P, V2, H>
+    where
+        P: Clone,
+        V2: Encodable,
+        F: Fn(&V) -> V2,
+    {
+        let mut tree = PatriciaMerkleTree {
+            root_ref: self.root_ref,
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(index, node)| (index, node.convert()))
+                .collect(),
+            values: self
+                .values
+                .iter()
+                .map(|(index, (path, value))| (index, (path.clone(), f(value))))
+                .collect(),
+            value_index: None,
+            hash: (false, Default::default()),
+        };
+
+        if self.value_index.is_some() {
+            tree.enable_value_index();
+        }
+        tree
+    }
+
+    /// Return a read-only view of the tree.
+    pub fn root_view(&self) -> RootView<'_, P, V, H> {
+        RootView::new(&self.nodes, &self.values, self.root_ref)
+    }
+
+    /// Return a reference to the root node (invalid if the tree is empty).
+    pub fn root_ref(&self) -> NodeRef {
+        self.root_ref
+    }
+
+    /// Append the RLP encoding of a node to `out`, returning false if there's no such node.
+    ///
+    /// The nibble offset at which the node sits within the tree is required because leaves only
+    /// encode the remaining part of their paths.
+    pub fn encode_node_into(
+        &self,
+        node_ref: NodeRef,
+        key_offset: usize,
+        out: &mut Vec<u8>,
+    ) -> bool {
+        match self.nodes.get(*node_ref) {
+            Some(node) => {
+                node.encode_into(&self.nodes, &self.values, key_offset, out);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return the RLP encoding of every hashed node, keyed by its hash.
+    ///
+    /// Nodes whose encodings are shorter than a hash are inlined into their parents' encodings, and
+    /// therefore have no entry of their own. The root is always included, since the root hash is
+    /// always hashed.
+    pub fn encode_all(&self) -> HashMap<Output<H>, Vec<u8>> {
+        let mut encodings = HashMap::new();
+        self.visit_hashed_nodes(|hash, encoding| {
+            encodings.insert(hash, encoding);
+            true
+        });
+
+        encodings
+    }
+
+    /// Return the hashed nodes (as in `encode_all`) which are not present in `base`, along with
+    /// their RLP encodings.
+    ///
+    /// Subtrees whose root is present in `base` are skipped entirely, since their descendants are
+    /// present there too. Nodes are returned parents first.
+    pub fn nodes_changed_since(&self, base: &Self) -> Vec<(Output<H>, Vec<u8>)> {
+        let base_encodings = base.encode_all();
+
+        let mut changes = Vec::new();
+        self.visit_hashed_nodes(|hash, encoding| {
+            if base_encodings.contains_key(&hash) {
+                return false;
+            }
+
+            changes.push((hash, encoding));
+            true
+        });
+
+        changes
+    }
+
+    /// Call `f` with the hash and encoding of every hashed node, parents first. Returning `false`
+    /// skips the node's descendants.
+    fn visit_hashed_nodes(&self, mut f: impl FnMut(Output<H>, Vec<u8>) -> bool) {
+        let mut stack = Vec::new();
+        if self.root_ref.is_valid() {
+            stack.push((self.root_ref, 0));
+        }
+        while let Some((node_ref, key_offset)) = stack.pop() {
+            let node = self
+                .nodes
+                .get(*node_ref)
+                .expect("inconsistent internal tree structure");
+
+            let mut encoding = Vec::new();
+            node.encode_into(&self.nodes, &self.values, key_offset, &mut encoding);
+            if (node_ref == self.root_ref || encoding.len() >= INLINE_THRESHOLD)
+                && !f(H::digest(&encoding), encoding)
+            {
+                continue;
+            }
+
+            match node {
+                Node::Branch(branch_node) => stack.extend(
+                    branch_node
+                        .choices
+                        .iter()
+                        .filter(|choice| choice.is_valid())
+                        .map(|choice| (*choice, key_offset + 1)),
+                ),
+                Node::Extension(extension_node) => stack.push((
+                    extension_node.child_ref,
+                    key_offset + extension_node.prefix.len(),
+                )),
+                Node::Leaf(_) => {}
+            }
+        }
+    }
+
+    /// Return the RLP encodings of the nodes on the way to a path, from the root down to the node
+    /// holding its value, or to the node proving its absence.
+    ///
+    /// Nodes inlined into their parents' encodings are included as well, so that a verifier can
+    /// check every step without decoding them. The proof of an empty tree is empty.
+    ///
+    /// Only the nodes on the path are encoded: their children's hashes are taken from the cache, and
+    /// the missing ones are computed and cached as well. Therefore proofs from a hashed tree (or
+    /// repeated proofs for the same path) don't compute any hash.
+    pub fn get_proof(&self, path: &[u8]) -> Vec<Vec<u8>> {
+        self.root_view().get_proof(path)
+    }
+
+    /// Retrieve a value from the tree given its path as a byte slice, along with its proof (as in
+    /// `get_proof`), walking the tree once.
+    pub fn get_with_proof(&self, path: &[u8]) -> (Option<&V>, Vec<Vec<u8>>) {
+        self.root_view().get_with_proof(path)
+    }
+
+    /// Return the total length of the RLP encodings of the nodes on the way to a path, without
+    /// encoding them.
+    ///
+    /// The walk stops at the node holding the path's value, or at the node proving its absence.
+    /// Children's hashes are computed (and cached) as needed, so it's cheap once the tree is hashed.
+    pub fn proof_size_estimate(&self, path: &[u8]) -> usize {
+        self.root_view()
+            .proof_path(path)
+            .into_iter()
+            .map(|(node_ref, key_offset)| {
+                let mut encoded_len = EncodedLen::default();
+                self.nodes
+                    .get(*node_ref)
+                    .expect("inconsistent internal tree structure")
+                    .encode_into(&self.nodes, &self.values, key_offset, &mut encoded_len);
+                encoded_len.0
+            })
+            .sum()
+    }
+
+    /// Return a node's hash as used within its parent: inlined if its encoding is shorter than a
+    /// hash, hashed otherwise (unlike the root hash, which is always hashed).
+    #[cfg(test)]
+    pub(crate) fn raw_node_hash_ref(
+        &self,
+        node_ref: NodeRef,
+        key_offset: usize,
+    ) -> NodeHashRef<'_, H> {
+        self.nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure")
+            .compute_hash(&self.nodes, &self.values, key_offset)
+    }
+
+    /// Return the cached root hash, or `None` if it needs to be recomputed.
+    pub fn peek_root(&self) -> Option<RootHash<H>> {
+        self.hash.0.then(|| self.hash.1.clone().into())
+    }
+
+    /// Return the root hash of the tree (or recompute if needed).
+    pub fn compute_hash(&mut self) -> RootHash<H> {
+        if !self.hash.0 {
+            if self.root_ref.is_valid() {
+                let root_node = self
+                    .nodes
+                    .get(*self.root_ref)
+                    .expect("inconsistent internal tree structure");
+
+                match root_node.compute_hash(&self.nodes, &self.values, 0) {
+                    NodeHashRef::Inline(x) => {
+                        H::new().chain_update(&*x).finalize_into(&mut self.hash.1)
+                    }
+                    NodeHashRef::Hashed(x) => self.hash.1.copy_from_slice(&x),
+                }
+
+                self.hash.0 = true;
+            } else {
+                H::new()
+                    .chain_update([0x80])
+                    .finalize_into(&mut self.hash.1);
+
+                self.hash.0 = true;
+            }
+        }
+
+        self.hash.1.clone().into()
+    }
+
+    /// Walk the tree and collect advisory structural issues (non-canonical node arrangements).
+    ///
+    /// An issue-free report doesn't imply the tree's contents are valid, only that its shape is the
+    /// one the insertion logic should produce.
+    pub fn integrity_report(&self) -> Vec<StructuralIssue> {
+        let mut issues = Vec::new();
+        if self.root_ref.is_valid() {
+            integrity::collect_issues(&self.nodes, self.root_ref, &mut issues);
+        }
+
+        issues
+    }
+
+    /// Check the tree's structure, then recompute its root hash from scratch (ignoring every cached
+    /// hash) and compare it against a claimed one.
+    pub fn verify_structure(&mut self, claimed_root: &[u8]) -> Result<(), VerifyError> {
+        let issues = self.integrity_report();
+        if !issues.is_empty() {
+            return Err(VerifyError::StructuralIssues(issues));
+        }
+
+        self.hash.0 = false;
+        for (_, node) in &mut self.nodes {
+            match node {
+                Node::Branch(branch_node) => branch_node.hash.mark_as_dirty(),
+                Node::Extension(extension_node) => extension_node.hash.mark_as_dirty(),
+                Node::Leaf(leaf_node) => leaf_node.hash.mark_as_dirty(),
+            }
+        }
+
+        let root_hash = self.compute_hash();
+        if root_hash.as_slice() == claimed_root {
+            Ok(())
+        } else {
+            Err(VerifyError::RootMismatch(RootMismatch {
+                expected: claimed_root.to_vec(),
+                actual: root_hash.as_slice().to_vec(),
+            }))
+        }
+    }
+
+    /// Count the entries under each path prefix of `depth` nibbles.
+    ///
+    /// Prefixes are returned as nibble sequences (one nibble per byte). Paths shorter than `depth`
+    /// are counted under their full nibble sequence.
+    pub fn prefix_histogram(&self, depth: usize) -> BTreeMap<Vec<u8>, usize> {
+        let mut histogram = BTreeMap::new();
+        for (path, _) in self.iter() {
+            let prefix = NibbleSlice::new(path.as_ref())
+                .take(depth)
+                .map(u8::from)
+                .collect::<Vec<_>>();
+
+            *histogram.entry(prefix).or_insert(0) += 1;
+        }
+
+        histogram
+    }
+
+    /// Count the leaves at each nibble depth, where index `d` holds the leaves at depth `d`.
+    ///
+    /// Values stored in branches are not counted.
+    pub fn depth_distribution(&self) -> Vec<usize> {
+        let mut distribution = Vec::new();
+
+        let mut stack = Vec::new();
+        if self.root_ref.is_valid() {
+            stack.push((self.root_ref, 0));
+        }
+        while let Some((node_ref, depth)) = stack.pop() {
+            let node = self
+                .nodes
+                .get(*node_ref)
+                .expect("inconsistent internal tree structure");
+
+            match node {
+                Node::Branch(branch_node) => stack.extend(
+                    branch_node
+                        .choices
+                        .iter()
+                        .filter(|x| x.is_valid())
+                        .map(|x| (*x, depth + 1)),
+                ),
+                Node::Extension(extension_node) => stack.push((
+                    extension_node.child_ref,
+                    depth + extension_node.prefix.len(),
+                )),
+                Node::Leaf(_) => {
+                    if distribution.len() <= depth {
+                        distribution.resize(depth + 1, 0);
+                    }
+                    distribution[depth] += 1;
+                }
+            }
+        }
+
+        distribution
+    }
+
+    /// Return the roots of the maximal subtrees whose nodes are all dirty, along with their node
+    /// counts.
+    ///
+    /// The nodes above them, up to the root, are dirty too but also have clean descendants, so
+    /// they're not reported. Their hashes depend on the reported subtrees', which makes them the last
+    /// to be flushed.
+    pub fn dirty_subtrees(&self) -> Vec<(NodeRef, usize)> {
+        let mut subtrees = Vec::new();
+        if self.root_ref.is_valid() {
+            if let Some(size) = self.dirty_subtree_size(self.root_ref, &mut subtrees) {
+                subtrees.push((self.root_ref, size));
+            }
+        }
+
+        subtrees
+    }
+
+    /// Return the node count of a subtree if all its nodes are dirty, otherwise report its maximal
+    /// dirty subtrees into `subtrees`.
+    fn dirty_subtree_size(
+        &self,
+        node_ref: NodeRef,
+        subtrees: &mut Vec<(NodeRef, usize)>,
+    ) -> Option<usize> {
+        let node = self
+            .nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure");
+
+        let child_sizes = match node {
+            Node::Branch(branch_node) => branch_node
+                .choices
+                .iter()
+                .filter(|x| x.is_valid())
+                .map(|x| (*x, self.dirty_subtree_size(*x, subtrees)))
+                .collect(),
+            Node::Extension(extension_node) => vec![(
+                extension_node.child_ref,
+                self.dirty_subtree_size(extension_node.child_ref, subtrees),
+            )],
+            Node::Leaf(_) => Vec::new(),
+        };
+
+        if node.hash().is_dirty() && child_sizes.iter().all(|(_, size)| size.is_some()) {
+            return Some(
+                1 + child_sizes
+                    .iter()
+                    .filter_map(|(_, size)| *size)
+                    .sum::<usize>(),
+            );
+        }
+
+        subtrees.extend(
+            child_sizes
+                .into_iter()
+                .filter_map(|(child_ref, size)| Some((child_ref, size?))),
+        );
+        None
+    }
+
+    /// Calculate approximated memory usage (both used and allocated).
+    ///
+    /// Both figures include the arenas and the values' bytes. Paths only count as the
+    /// `size_of::<P>()` of their slots, since the tree can't tell whether it owns the bytes behind
+    /// them (as with `&[u8]` paths). Values are measured by their `Encodable::encoded_len()`, which
+    /// encodes each of them unless their type overrides it, and counts borrowed bytes as well.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let data_len: usize = self
+            .values
+            .iter()
+            .map(|(_, (_, value))| value.encoded_len())
+            .sum();
+
+        let mem_consumed = size_of::<Node<P, V, H>>() * self.nodes.len()
+            + size_of::<(P, V)>() * self.values.len()
+            + data_len;
+        let mem_reserved = size_of::<Node<P, V, H>>() * self.nodes.capacity()
+            + size_of::<(P, V)>() * self.values.capacity()
+            + data_len;
+
+        (mem_consumed, mem_reserved)
+    }
+
+    /// Use after a `.clone()` to reserve the capacity the slabs would have if they hadn't been
+    /// cloned.
+    ///
+    /// Note: Used by the benchmark to mimic real conditions.
+    #[doc(hidden)]
+    pub fn reserve_next_power_of_two(&mut self) {
+        self.nodes
+            .reserve(self.nodes.capacity().next_power_of_two());
+        self.values
+            .reserve(self.values.capacity().next_power_of_two());
+    }
+}
+
+impl<V, H> ByteTrie<V, H>
+where
+    V: Encodable,
+    H: Digest,
+{
+    /// Build a tree from a sorted map.
+    pub fn from_btreemap(map: BTreeMap<Vec<u8>, V>) -> Self {
+        let mut tree = Self::new();
+        for (path, value) in map {
+            tree.insert(path, value);
+        }
+
+        tree
+    }
+
+    /// Copy the entries under a path prefix into a new tree, with the prefix stripped from their
+    /// paths. Return `None` if there are no such entries.
+    pub fn clone_subtree(&self, prefix: &[u8]) -> Option<Self>
+    where
+        V: Clone,
+    {
+        let node_ref = self.find_prefix_node(prefix)?;
+
+        let mut tree = Self::new();
+        for value_ref in ValueRefs::new(&self.nodes, node_ref) {
+            let (path, value) = self
+                .values
+                .get(*value_ref)
+                .expect("inconsistent internal tree structure");
+
+            // Only leaves may have paths not matching the prefix.
+            if let Some(path) = path.strip_prefix(prefix) {
+                tree.insert(path.to_vec(), value.clone());
+            }
+        }
+
+        (!tree.is_empty()).then_some(tree)
+    }
+
+    /// Find the topmost node whose subtree contains every path starting with a prefix.
+    fn find_prefix_node(&self, prefix: &[u8]) -> Option<NodeRef> {
+        let mut prefix = NibbleSlice::new(prefix);
+        let mut node_ref = self.root_ref;
+        loop {
+            match self.nodes.get(*node_ref)? {
+                Node::Branch(branch_node) => match prefix.next() {
+                    Some(nibble) => node_ref = branch_node.choices[nibble as usize],
+                    None => return Some(node_ref),
+                },
+                Node::Extension(extension_node) => {
+                    // A prefix ending within the extension still covers its whole subtree.
+                    for nibble in extension_node.prefix.iter() {
+                        match prefix.next() {
+                            Some(x) if x == nibble => {}
+                            Some(_) => return None,
+                            None => return Some(node_ref),
+                        }
+                    }
+
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(_) => return Some(node_ref),
+            }
+        }
+    }
+
+    /// Insert a value into the tree given its path as a byte slice.
+    pub fn insert_bytes(&mut self, path: &[u8], value: V) -> Option<V> {
+        self.insert(path.to_vec(), value)
+    }
+
+    /// Insert a value into the tree given its path as a string.
+    pub fn insert_str(&mut self, path: &str, value: V) -> Option<V> {
+        self.insert_bytes(path.as_bytes(), value)
+    }
+}
+
+impl<P, V, H> Default for PatriciaMerkleTree<P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, P, V, H> IntoIterator for &'a PatriciaMerkleTree<P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = (&'a P, &'a V);
+    type IntoIter = Iter<'a, P, V, H>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<P, V, H> IntoIterator for PatriciaMerkleTree<P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    type Item = (P, V);
+    type IntoIter = IntoIter<P, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(&self.nodes, self.values, self.root_ref)
+    }
+}
+
+impl<P, H> PatriciaMerkleTree<P, Vec<u8>, H>
+where
+    P: AsRef<[u8]>,
+    H: Digest,
+{
+    /// Release the excess capacity of the stored value buffers.
+    ///
+    /// The stored bytes are not modified, therefore no hashes are invalidated.
+    pub fn shrink_values(&mut self) {
+        self.values
+            .iter_mut()
+            .for_each(|(_, (_, value))| value.shrink_to_fit());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, sync::Arc};
+
+    use crate::*;
+    use digest::{FixedOutput, HashMarker, OutputSizeUser, Update};
+    use hex_literal::hex;
+    use proptest::collection::{btree_set, vec};
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, RngCore, SeedableRng};
+    use sha3::{Keccak256, Sha3_256, Sha3_512};
+
+    #[test]
+    fn compute_hash_empty() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+
+        assert!(!tree.root_ref().is_valid());
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            hex!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+        );
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            Keccak256::digest([0x80]).as_slice()
+        );
+
+        // A single empty leaf is not the same as an empty tree.
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"", b"");
+
+        assert_ne!(
+            tree.compute_hash().as_slice(),
+            Keccak256::digest([0x80]).as_slice()
+        );
+    }
+
+    #[test]
+    fn default_is_empty() {
+        // Neither the path nor the digest need to implement `Default`.
+        struct Path(Vec<u8>);
+        impl AsRef<[u8]> for Path {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        let mut tree = PatriciaMerkleTree::<Path, &[u8], Keccak256>::default();
+        assert!(tree.is_empty());
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            hex!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+        );
+
+        tree.insert(Path(b"first".to_vec()), b"value");
+        assert_eq!(tree.get_bytes(b"first"), Some(&&b"value"[..]));
+    }
+
+    #[test]
+    fn compute_hash() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+
+        tree.insert(b"first", b"value");
+        tree.insert(b"second", b"value");
+
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            hex!("f7537e7f4b313c426440b7fface6bff76f51b3eb0d127356efbe6f2b3c891501"),
+        );
+    }
+
+    #[test]
+    fn compute_hash_long() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+
+        tree.insert(b"first", b"value");
+        tree.insert(b"second", b"value");
+        tree.insert(b"third", b"value");
+        tree.insert(b"fourth", b"value");
+
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            hex!("e2ff76eca34a96b68e6871c74f2a5d9db58e59f82073276866fdd25e560cedea"),
+        );
+    }
+
+    #[test]
+    fn insert_identical_value_keeps_hashes() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+
+        tree.insert(b"first", b"value");
+        tree.insert(b"second", b"value");
+        tree.compute_hash();
+
+        assert_eq!(tree.insert(b"first", b"value"), Some(&b"value"[..]));
+        assert!(tree.peek_root().is_some());
+        assert!(tree
+            .nodes
+            .iter()
+            .all(|(_, node)| node.hash().extract_ref().is_some()));
+
+        assert_eq!(tree.insert(b"first", b"other"), Some(&b"value"[..]));
+        assert!(tree.peek_root().is_none());
+
+        let mut expected = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        expected.insert(b"first", b"other");
+        expected.insert(b"second", b"value");
+        assert_eq!(tree.compute_hash(), expected.compute_hash());
+    }
+
+    #[test]
+    fn shrink_values() {
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+
+        tree.insert(b"first", vec![0xAA; 4096]);
+        tree.insert(b"second", b"value".to_vec());
+
+        let mut value = tree.insert(b"first", Vec::new()).unwrap();
+        value.truncate(1);
+        tree.insert(b"first", value);
+        assert!(tree.get(&&b"first"[..]).unwrap().capacity() >= 4096);
+
+        let hash = tree.compute_hash();
+        tree.shrink_values();
+
+        assert!(tree.get(&&b"first"[..]).unwrap().capacity() < 4096);
+        assert_eq!(tree.get(&&b"first"[..]), Some(&vec![0xAA]));
+        assert_eq!(tree.peek_root(), Some(hash));
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        assert_eq!(tree.memory_usage().0, 0);
+
+        let mut usages = Vec::new();
+        for i in 0..4u32 {
+            for j in 0..256u32 {
+                tree.insert((i * 256 + j).to_be_bytes().to_vec(), vec![0xAA; 32]);
+            }
+            usages.push(tree.memory_usage());
+        }
+
+        // Every batch of entries takes roughly the same amount of memory.
+        let first_batch = usages[0].0;
+        assert!(first_batch >= 256 * (size_of::<(Vec<u8>, Vec<u8>)>() + 32));
+        for (prev, next) in usages.iter().zip(&usages[1..]) {
+            let batch = next.0 - prev.0;
+            assert!(batch > first_batch / 2 && batch < first_batch * 2);
+            assert!(next.1 >= next.0);
+        }
+
+        // Borrowed paths only count as their slots, whatever their length.
+        let paths = [[0x12; 4], [0x34; 4]];
+        let long_paths = [[0x12; 400], [0x34; 400]];
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        let mut long_tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for (path, long_path) in paths.iter().zip(&long_paths) {
+            tree.insert(path, b"value");
+            long_tree.insert(long_path, b"value");
+        }
+        assert_eq!(tree.memory_usage().0, long_tree.memory_usage().0);
+    }
+
+    #[test]
+    fn insert_with_hash() {
+        let data = (0..64u8)
+            .map(|x| (vec![x.wrapping_mul(37), x], vec![x; 40]))
+            .collect::<Vec<_>>();
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        let mut hashed_tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for (path, value) in data {
+            tree.insert(path.clone(), value.clone());
+
+            let (leaf_node, key_offset) = tree.find_leaf(&path).unwrap();
+            let leaf_hash = match leaf_node.compute_hash(&tree.nodes, &tree.values, key_offset) {
+                NodeHashRef::Hashed(x) => *x,
+                NodeHashRef::Inline(_) => panic!("expected a hashed leaf"),
+            };
+
+            hashed_tree.insert_with_hash(path.clone(), value, &leaf_hash);
+            let (leaf_node, _) = hashed_tree.find_leaf(&path).unwrap();
+            assert!(leaf_node.hash.extract_ref().is_some());
+        }
+
+        assert_eq!(hashed_tree.compute_hash(), tree.compute_hash());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid precomputed leaf hash")]
+    fn insert_with_hash_invalid() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert_with_hash(b"first", &[0xAA; 40], &Default::default());
+    }
+
+    #[test]
+    fn iter_sorted() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = (0..200)
+            .map(|_| {
+                let mut path = vec![0; rng.gen_range(1..4)];
+                rng.fill_bytes(&mut path);
+                (path, rng.next_u32().to_be_bytes().to_vec())
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        assert_eq!(tree.iter().next(), None);
+        for (path, value) in &data {
+            tree.insert(path.clone(), value.clone());
+        }
+
+        let mut borrowed = Vec::new();
+        for (path, value) in &tree {
+            borrowed.push((path.clone(), value.clone()));
+        }
+        assert_eq!(borrowed, data.clone().into_iter().collect::<Vec<_>>());
+
+        let mut owned = Vec::new();
+        for (path, value) in tree {
+            owned.push((path, value));
+        }
+        assert_eq!(owned, data.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn raw_node_hash_ref_inline_boundary() {
+        // A leaf at the root with a single-byte path encodes as `[list, [0x20, path], value]`,
+        // which takes 5 bytes plus the value's length.
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(&[0x01], vec![0xAA; 26]);
+
+        let mut encoding = Vec::new();
+        tree.encode_node_into(tree.root_ref(), 0, &mut encoding);
+        assert_eq!(encoding.len(), 31);
+        match tree.raw_node_hash_ref(tree.root_ref(), 0) {
+            NodeHashRef::Inline(x) => assert_eq!(&*x, encoding.as_slice()),
+            NodeHashRef::Hashed(_) => panic!("expected an inline node"),
+        }
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            Keccak256::digest(&encoding).as_slice(),
+        );
+
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(&[0x01], vec![0xAA; 27]);
+
+        let mut encoding = Vec::new();
+        tree.encode_node_into(tree.root_ref(), 0, &mut encoding);
+        assert_eq!(encoding.len(), 32);
+        match tree.raw_node_hash_ref(tree.root_ref(), 0) {
+            NodeHashRef::Inline(_) => panic!("expected a hashed node"),
+            NodeHashRef::Hashed(x) => assert_eq!(*x, Keccak256::digest(&encoding)),
+        }
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            Keccak256::digest(&encoding).as_slice(),
+        );
+    }
+
+    #[test]
+    fn get_closest() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.get_closest(&[0x12]), None);
+
+        for path in [
+            &[0x12, 0x34][..],
+            &[0x12, 0x36],
+            &[0x12, 0x3C, 0x00],
+            &[0x12, 0x3C, 0xF0],
+            &[0x56],
+            &[0x56, 0x70],
+            &[0x90, 0x00, 0x01],
+        ] {
+            tree.insert(path, path);
+        }
+
+        let closest = |path: &[u8]| tree.get_closest(path).map(|(x, _)| *x);
+
+        // Exact matches.
+        assert_eq!(closest(&[0x12, 0x36]), Some(&[0x12, 0x36][..]));
+        assert_eq!(closest(&[0x56]), Some(&[0x56][..]));
+        // Between two choices, the closest one wins.
+        assert_eq!(closest(&[0x12, 0x35]), Some(&[0x12, 0x34][..]));
+        assert_eq!(closest(&[0x12, 0x3A]), Some(&[0x12, 0x3C, 0x00][..]));
+        assert_eq!(closest(&[0x12, 0x3F]), Some(&[0x12, 0x3C, 0xF0][..]));
+        // Within the same distance, the smaller choice wins.
+        assert_eq!(closest(&[0x12, 0x38]), Some(&[0x12, 0x36][..]));
+        assert_eq!(closest(&[0x30]), Some(&[0x12, 0x3C, 0xF0][..]));
+        // A branch's value diverges below nibble zero.
+        assert_eq!(closest(&[0x56, 0x00]), Some(&[0x56][..]));
+        assert_eq!(closest(&[0x56, 0x60]), Some(&[0x56, 0x70][..]));
+        // Diverging within an extension.
+        assert_eq!(closest(&[0x90, 0x10]), Some(&[0x90, 0x00, 0x01][..]));
+        assert_eq!(closest(&[0x90]), Some(&[0x90, 0x00, 0x01][..]));
+        // Shorter than the stored paths.
+        assert_eq!(closest(&[0x12]), Some(&[0x12, 0x34][..]));
+    }
+
+    #[test]
+    fn to_sorted_vec() {
+        let mut rng = StdRng::seed_from_u64(2);
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for _ in 0..100 {
+            let mut path = vec![0; rng.gen_range(1..4)];
+            rng.fill_bytes(&mut path);
+            tree.insert(path, rng.next_u64().to_be_bytes().to_vec());
+        }
+
+        let sorted = tree.to_sorted_vec();
+        assert_eq!(sorted.len(), tree.len());
+        assert!(sorted.windows(2).all(|x| x[0].0 < x[1].0));
+
+        for _ in 0..100 {
+            let mut path = vec![0; rng.gen_range(1..4)];
+            rng.fill_bytes(&mut path);
+
+            let value = sorted
+                .binary_search_by(|(x, _)| x.cmp(&path))
+                .ok()
+                .map(|index| &sorted[index].1);
+            assert_eq!(value, tree.get(&path));
+        }
+        for (path, value) in &sorted {
+            assert_eq!(tree.get(path), Some(value));
+        }
+    }
+
+    #[test]
+    fn btreemap_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for _ in 0..100 {
+            let mut path = vec![0; rng.gen_range(1..8)];
+            rng.fill_bytes(&mut path);
+            tree.insert(path, rng.next_u64().to_be_bytes().to_vec());
+        }
+
+        let map = tree.to_btreemap();
+        assert_eq!(map.len(), tree.len());
+
+        let mut other_tree = ByteTrie::<Vec<u8>, Keccak256>::from_btreemap(map.clone());
+        assert_eq!(other_tree.compute_hash(), tree.compute_hash());
+        assert_eq!(other_tree.to_btreemap(), map);
+    }
+
+    #[test]
+    fn compute_hash_64_byte_digest() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Sha3_512>::new();
+        tree.insert(b"first", &[0xAA; 40]);
+        tree.insert(b"second", &[0xBB; 40]);
+        tree.insert(b"s", b"short");
+
+        // Every child is referenced by its 64-byte hash, except the short one which is inlined.
+        let root_node = match &tree.nodes[*tree.root_ref] {
+            Node::Branch(branch_node) => branch_node,
+            _ => panic!("expected a branch node"),
+        };
+        let mut expected_encoding = Vec::new();
+        for choice in root_node.choices.iter().filter(|x| x.is_valid()) {
+            let mut child_encoding = Vec::new();
+            tree.encode_node_into(*choice, 1, &mut child_encoding);
+
+            match tree.raw_node_hash_ref(*choice, 1) {
+                NodeHashRef::Hashed(x) => {
+                    assert_eq!(x.len(), 64);
+                    assert_eq!(*x, Sha3_512::digest(&child_encoding));
+
+                    expected_encoding.extend_from_slice(&[0xB8, 0x40]);
+                    expected_encoding.extend_from_slice(&x);
+                }
+                NodeHashRef::Inline(x) => expected_encoding.extend_from_slice(&x),
+            }
+        }
+
+        let mut encoding = Vec::new();
+        tree.encode_node_into(tree.root_ref(), 0, &mut encoding);
+        assert!(encoding
+            .windows(expected_encoding.len())
+            .any(|x| x == expected_encoding));
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            Sha3_512::digest(&encoding).as_slice(),
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.remove(&&b"first"[..]), None);
+
+        tree.insert(b"first", b"a");
+        tree.insert(b"firstly", b"b");
+        tree.insert(b"second", b"c");
+        tree.compute_hash();
+
+        assert_eq!(tree.remove(&&b"fir"[..]), None);
+        assert_eq!(tree.remove(&&b"third"[..]), None);
+        assert!(tree.peek_root().is_some());
+
+        // The branch holding `first` collapses into a leaf, and the extension above it too.
+        assert_eq!(tree.remove(&&b"first"[..]), Some(&b"a"[..]));
+        assert_eq!(tree.peek_root(), None);
+        assert_eq!(tree.get(&&b"first"[..]), None);
+        assert_eq!(tree.get(&&b"firstly"[..]), Some(&&b"b"[..]));
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.nodes.len(), 3);
+
+        let mut expected = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        expected.insert(b"firstly", b"b");
+        expected.insert(b"second", b"c");
+        assert_eq!(tree.compute_hash(), expected.compute_hash());
+
+        assert_eq!(tree.remove(&&b"second"[..]), Some(&b"c"[..]));
+        assert_eq!(tree.remove(&&b"firstly"[..]), Some(&b"b"[..]));
+        assert!(tree.is_empty());
+        assert!(tree.values.is_empty());
+        assert_eq!(
+            tree.compute_hash(),
+            PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new().compute_hash(),
+        );
+    }
+
+    #[test]
+    fn remove_batch() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        let mut expected = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for i in 0..1000u32 {
+            let path = Keccak256::digest(i.to_be_bytes()).to_vec();
+            tree.insert(path.clone(), i.to_be_bytes().to_vec());
+            if i % 2 == 1 {
+                expected.insert(path, i.to_be_bytes().to_vec());
+            }
+        }
+        tree.compute_hash();
+
+        // Missing and repeated paths are not counted.
+        let paths = (0..1000u32)
+            .step_by(2)
+            .chain([0, 2000])
+            .map(|i| Keccak256::digest(i.to_be_bytes()).to_vec());
+        assert_eq!(tree.remove_batch(paths), 500);
+        assert_eq!(tree.len(), 500);
+        assert_eq!(tree.nodes.len(), expected.nodes.len());
+        assert_eq!(tree.compute_hash(), expected.compute_hash());
+    }
+
+    #[test]
+    fn remove_reindexes_value() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.enable_value_index();
+        tree.insert(b"first", b"a");
+        tree.insert(b"second", b"a");
+
+        tree.remove(&&b"first"[..]);
+        assert_eq!(tree.keys_for_value(b"a"), [b"second"]);
+
+        tree.remove(&&b"second"[..]);
+        assert!(tree.keys_for_value(b"a").is_empty());
+        assert!(tree.value_index.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn insert_if_absent() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"first", b"value");
+        let hash = tree.compute_hash();
+
+        assert!(!tree.insert_if_absent(b"first", b"other"));
+        assert_eq!(tree.get(&&b"first"[..]), Some(&&b"value"[..]));
+        assert_eq!(tree.peek_root(), Some(hash));
+
+        assert!(tree.insert_if_absent(b"second", b"value"));
+        assert_eq!(tree.get(&&b"second"[..]), Some(&&b"value"[..]));
+        assert_eq!(tree.peek_root(), None);
+        assert_ne!(tree.compute_hash(), hash);
+
+        // Present paths held by branches are kept too, along with every cached hash.
+        tree.insert(b"firstborn", b"value");
+        let hash = tree.compute_hash();
+        let node_count = tree.nodes.len();
+
+        assert!(!tree.insert_if_absent(b"first", b"other"));
+        assert_eq!(tree.get(&&b"first"[..]), Some(&&b"value"[..]));
+        assert_eq!(tree.peek_root(), Some(hash));
+        assert_eq!(tree.nodes.len(), node_count);
+        assert!(tree
+            .nodes
+            .iter()
+            .all(|(_, node)| node.hash().extract_ref().is_some()));
+    }
+
+    #[test]
+    fn clone_subtree() {
+        let mut tree = ByteTrie::<&[u8], Keccak256>::new();
+        for path in [
+            &[0x12, 0x34, 0x56][..],
+            &[0x12, 0x34, 0x78],
+            &[0x12, 0x34],
+            &[0x12, 0x35, 0x00],
+            &[0x9A],
+        ] {
+            tree.insert(path.to_vec(), path);
+        }
+
+        let check_subtree = |prefix: &[u8], expected: &[&[u8]]| {
+            let mut subtree = tree.clone_subtree(prefix).unwrap();
+            assert_eq!(
+                subtree
+                    .iter()
+                    .map(|(x, _)| x.as_slice())
+                    .collect::<Vec<_>>(),
+                expected,
+            );
+            for (path, value) in subtree.iter() {
+                assert_eq!(*value, [prefix, path].concat());
+            }
+
+            let mut expected_tree = ByteTrie::<&[u8], Keccak256>::new();
+            for path in expected {
+                expected_tree.insert(
+                    path.to_vec(),
+                    tree.get_bytes(&[prefix, path].concat()).unwrap(),
+                );
+            }
+            assert_eq!(subtree.compute_hash(), expected_tree.compute_hash());
+        };
+
+        // Ending within an extension.
+        check_subtree(
+            &[0x12],
+            &[&[0x34], &[0x34, 0x56], &[0x34, 0x78], &[0x35, 0x00]],
+        );
+        // Ending at a branch with a value.
+        check_subtree(&[0x12, 0x34], &[&[], &[0x56], &[0x78]]);
+        // Ending at a leaf.
+        check_subtree(&[0x12, 0x35], &[&[0x00]]);
+        check_subtree(&[0x12, 0x35, 0x00], &[&[]]);
+        check_subtree(
+            &[],
+            &[
+                &[0x12, 0x34],
+                &[0x12, 0x34, 0x56],
+                &[0x12, 0x34, 0x78],
+                &[0x12, 0x35, 0x00],
+                &[0x9A],
+            ],
+        );
+
+        assert!(tree.clone_subtree(&[0x13]).is_none());
+        assert!(tree.clone_subtree(&[0x12, 0x35, 0x01]).is_none());
+        assert!(tree.clone_subtree(&[0x9A, 0x00]).is_none());
+    }
+
+    #[test]
+    fn get_kind() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(&[0x12], b"branch");
+        tree.insert(&[0x12, 0x34], b"leaf");
+        tree.insert(&[0x56], b"other");
+
+        assert_eq!(
+            tree.get_kind(&&[0x12][..]),
+            Some((TerminalKind::BranchValue, &&b"branch"[..])),
+        );
+        assert_eq!(
+            tree.get_kind(&&[0x12, 0x34][..]),
+            Some((TerminalKind::Leaf, &&b"leaf"[..])),
+        );
+        assert_eq!(
+            tree.get_kind(&&[0x56][..]),
+            Some((TerminalKind::Leaf, &&b"other"[..])),
+        );
+        assert_eq!(tree.get_kind(&&[0x12, 0x35][..]), None);
+        assert_eq!(tree.get_kind(&&[0x12, 0x99][..]), None);
+        assert_eq!(tree.get_kind(&&[0x57][..]), None);
+    }
+
+    #[test]
+    fn get_terminal_node() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(&[0x12], b"branch");
+        tree.insert(&[0x12, 0x34], b"leaf");
+
+        let leaf_node = match tree.get_terminal_node(&&[0x12, 0x34][..]) {
+            Some(Node::Leaf(leaf_node)) => leaf_node,
+            node => panic!("expected a leaf node, got {node:?}"),
+        };
+        assert_eq!(
+            tree.value_by_ref(leaf_node.value_ref()),
+            Some((&[0x12, 0x34][..], &&b"leaf"[..])),
+        );
+
+        assert!(matches!(
+            tree.get_terminal_node(&&[0x12][..]),
+            Some(Node::Branch(branch_node)) if branch_node.value_ref().is_valid(),
+        ));
+        assert!(tree.get_terminal_node(&&[0x12, 0x35][..]).is_none());
+    }
+
+    #[test]
+    fn swap_value() {
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(b"first", b"a".to_vec());
+        tree.insert(b"firstly", b"b".to_vec());
+        tree.insert(b"second", b"c".to_vec());
+        let hash = tree.compute_hash();
+
+        assert_eq!(tree.swap_value(&&b"third"[..], b"d".to_vec()), None);
+        assert_eq!(tree.swap_value(&&b"firs"[..], b"d".to_vec()), None);
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.peek_root(), Some(hash));
+
+        assert_eq!(
+            tree.swap_value(&&b"firstly"[..], vec![0xAA; 40]),
+            Some(b"b".to_vec()),
+        );
+        assert_eq!(tree.get(&&b"firstly"[..]), Some(&vec![0xAA; 40]));
+        assert_eq!(tree.peek_root(), None);
+
+        let mut expected_tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        expected_tree.insert(b"first", b"a".to_vec());
+        expected_tree.insert(b"firstly", vec![0xAA; 40]);
+        expected_tree.insert(b"second", b"c".to_vec());
+        assert_eq!(tree.compute_hash(), expected_tree.compute_hash());
+    }
+
+    #[test]
+    fn root_view() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"first", b"a");
+        tree.insert(b"second", b"b");
+
+        let view = tree.root_view();
+        let other_view = view;
+        assert_eq!(view.root_ref(), tree.root_ref());
+        assert_eq!(view.get(&&b"first"[..]), Some(&&b"a"[..]));
+        assert_eq!(other_view.get_bytes(b"third"), None);
+        assert!(view.iter().eq(tree.iter()));
+
+        let proof = view.get_proof(b"first");
+        assert_eq!(proof, tree.get_proof(b"first"));
+
+        let root_hash = tree.compute_hash();
+        assert_eq!(
+            Keccak256::digest(&proof[0]).as_slice(),
+            root_hash.as_slice()
+        );
+    }
+
+    #[test]
+    fn insert_checked() {
+        let entries: [(&[u8], &[u8]); 3] = [(b"first", b"a"), (b"second", b"b"), (b"third", b"c")];
+
+        let mut expected_tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        let expected_roots = entries.map(|(path, value)| {
+            expected_tree.insert(path, value);
+            expected_tree.compute_hash()
+        });
+
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(
+            tree.insert_checked(b"first", b"a", expected_roots[0].as_slice()),
+            Ok(()),
+        );
+        assert_eq!(
+            tree.insert_checked(b"second", b"x", expected_roots[1].as_slice()),
+            Err(RootMismatch {
+                expected: expected_roots[1].as_slice().to_vec(),
+                actual: tree.compute_hash().as_slice().to_vec(),
+            }),
+        );
+        assert_eq!(tree.get(&&b"second"[..]), Some(&&b"x"[..]));
+    }
+
+    #[test]
+    fn hash_of_value() {
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(b"first", b"value".to_vec());
+        tree.insert(b"second", vec![0xAA; 40]);
+
+        assert_eq!(
+            tree.hash_of_value(&&b"first"[..]),
+            Some(Keccak256::digest(b"value").into()),
+        );
+        assert_eq!(
+            tree.hash_of_value(&&b"second"[..])
+                .map(RootHash::into_inner),
+            Some(Keccak256::digest([0xAA; 40])),
+        );
+        assert_eq!(tree.hash_of_value(&&b"third"[..]), None);
+    }
+
+    #[test]
+    fn verify_structure() {
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(b"first", b"a".to_vec());
+        tree.insert(b"firstly", b"b".to_vec());
+        tree.insert(b"second", b"c".to_vec());
+
+        let root_hash = tree.compute_hash();
+        assert_eq!(tree.verify_structure(root_hash.as_slice()), Ok(()));
+
+        // Corrupt a value without invalidating any cached hash.
+        let (_, value) = tree.values.iter_mut().next().unwrap().1;
+        value.push(0x00);
+        assert_eq!(tree.peek_root(), Some(root_hash));
+
+        assert!(matches!(
+            tree.verify_structure(root_hash.as_slice()),
+            Err(VerifyError::RootMismatch(RootMismatch { expected, actual }))
+                if expected == root_hash.as_slice() && actual != expected,
+        ));
+    }
+
+    #[test]
+    fn keys_for_value() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"first", b"a");
+        tree.enable_value_index();
+
+        tree.insert(b"second", b"b");
+        tree.insert(b"third", b"a");
+        tree.insert(b"fourth", b"a");
+        tree.insert(b"fifth", b"b");
+        assert_eq!(
+            tree.keys_for_value(b"a"),
+            [&b"first"[..], b"fourth", b"third"],
+        );
+        assert_eq!(tree.keys_for_value(b"b"), [&b"fifth"[..], b"second"]);
+        assert_eq!(tree.keys_for_value(b"c"), Vec::<Vec<u8>>::new());
+
+        // Replacements move the paths between values.
+        tree.insert(b"third", b"c");
+        tree.swap_value(&&b"fifth"[..], b"a");
+        assert_eq!(
+            tree.keys_for_value(b"a"),
+            [&b"fifth"[..], b"first", b"fourth"],
+        );
+        assert_eq!(tree.keys_for_value(b"b"), [b"second"]);
+        assert_eq!(tree.keys_for_value(b"c"), [b"third"]);
+
+        // The full scan must agree with the index.
+        let mut other_tree = tree.clone();
+        other_tree.value_index = None;
+        for value in [&b"a"[..], b"b", b"c", b"d"] {
+            assert_eq!(other_tree.keys_for_value(value), tree.keys_for_value(value));
+        }
+    }
+
+    #[test]
+    fn extend_sorted() {
+        let data = (0..200u16)
+            .map(|x| (x.wrapping_mul(13).to_be_bytes().to_vec(), vec![x as u8; 8]))
+            .collect::<BTreeMap<_, _>>();
+        let (seed_data, extra_data) = data
+            .iter()
+            .partition::<Vec<_>, _>(|(path, _)| path[0] < 0x07);
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for (path, value) in seed_data {
+            tree.insert(path.clone(), value.clone());
+        }
+        tree.compute_hash();
+
+        tree.extend_sorted(
+            extra_data
+                .into_iter()
+                .map(|(path, value)| (path.clone(), value.clone())),
+        );
+        assert_eq!(tree.len(), data.len());
+
+        let mut expected_tree = ByteTrie::<Vec<u8>, Keccak256>::from_btreemap(data);
+        assert_eq!(tree.compute_hash(), expected_tree.compute_hash());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "paths are not ascending")]
+    fn extend_sorted_not_bigger() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"second", b"a");
+
+        tree.extend_sorted([(&b"third"[..], &b"b"[..]), (b"first", b"c")]);
+    }
+
+    #[test]
+    fn encode_all() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert!(tree.encode_all().is_empty());
+
+        // A small root is still included.
+        tree.insert(b"first", b"a");
+        let encodings = tree.encode_all();
+        assert_eq!(encodings.len(), 1);
+        assert!(encodings.contains_key(tree.compute_hash().as_slice()));
+
+        // The leaves with short values are inlined into their branch, unlike the one with a long value.
+        tree.insert(b"firstly", b"b");
+        tree.insert(b"fir", b"c");
+        tree.insert(b"second", &[0xAA; 40]);
+        let encodings = tree.encode_all();
+        assert!(encodings.len() < tree.nodes.len());
+        for (hash, encoding) in &encodings {
+            assert_eq!(hash, &Keccak256::digest(encoding));
+        }
+
+        let root_hash = tree.compute_hash();
+        let mut root_encoding = Vec::new();
+        assert!(tree.encode_node_into(tree.root_ref(), 0, &mut root_encoding));
+        assert_eq!(encodings.get(root_hash.as_slice()), Some(&root_encoding));
+
+        let leaf_ref = match &tree.nodes[*tree.root_ref] {
+            Node::Branch(branch_node) => branch_node.choices[7],
+            _ => panic!("expected a branch node"),
+        };
+        let mut leaf_encoding = Vec::new();
+        assert!(tree.encode_node_into(leaf_ref, 1, &mut leaf_encoding));
+        assert_eq!(
+            encodings.get(&Keccak256::digest(&leaf_encoding)),
+            Some(&leaf_encoding),
+        );
+    }
+
+    #[test]
+    fn dirty_subtrees() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert!(tree.dirty_subtrees().is_empty());
+
+        for path in [&[0x11][..], &[0x21], &[0x31]] {
+            tree.insert(path, path);
+        }
+        assert_eq!(tree.dirty_subtrees(), [(tree.root_ref(), 4)]);
+
+        tree.compute_hash();
+        assert!(tree.dirty_subtrees().is_empty());
+
+        // Each insertion turns a leaf into a branch with two leaves, while the third leaf stays clean.
+        tree.insert(&[0x12], &[0x12]);
+        tree.insert(&[0x22, 0x00], &[0x22, 0x00]);
+
+        let choices = match &tree.nodes[*tree.root_ref] {
+            Node::Branch(branch_node) => branch_node.choices,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(tree.dirty_subtrees(), [(choices[1], 3), (choices[2], 3)]);
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::with_capacity(100);
+        assert!(tree.is_empty());
+        assert!(tree.values.capacity() >= 100);
+        assert!(tree.nodes.capacity() >= 150);
+
+        tree.reserve(100);
+        assert!(tree.values.capacity() >= 100);
+        assert!(tree.nodes.capacity() >= 150);
+    }
+
+    #[test]
+    fn nodes_changed_since() {
+        let mut base = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for (path, value) in [
+            (&b"first"[..], &[0xAA; 40][..]),
+            (b"second", &[0xBB; 40]),
+            (b"third", &[0xCC; 40]),
+        ] {
+            base.insert(path, value);
+        }
+        assert!(base.nodes_changed_since(&base).is_empty());
+
+        let mut tree = base.clone();
+        tree.insert(b"second", &[0xDD; 40]);
+
+        // Only the changed leaf and the branches above it (`s` and `t` share their first nibble)
+        // are missing from the base.
+        let changes = tree.nodes_changed_since(&base);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].0.as_slice(), tree.compute_hash().as_slice());
+
+        // Together with the base nodes, they make up every node of the new tree.
+        let mut encodings = base.encode_all();
+        encodings.extend(changes);
+        let new_encodings = tree.encode_all();
+        assert!(new_encodings
+            .iter()
+            .all(|(hash, encoding)| encodings.get(hash) == Some(encoding)));
+    }
+
+    #[test]
+    fn split_off() {
+        let paths = (0..1000u32)
+            .map(|i| Keccak256::digest(i.to_be_bytes())[..4].to_vec())
+            .collect::<Vec<_>>();
+        let split_path = [0x80, 0x00];
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        let (mut left, mut right) = (tree.clone(), tree.clone());
+        for path in &paths {
+            tree.insert(path.clone(), path.clone());
+            if path.as_slice() < &split_path[..] {
+                left.insert(path.clone(), path.clone());
+            } else {
+                right.insert(path.clone(), path.clone());
+            }
+        }
+
+        let original = tree.to_btreemap();
+        let mut other = tree.split_off(&split_path);
+        assert!(!tree.is_empty() && !other.is_empty());
+        assert!(tree
+            .iter()
+            .all(|(path, _)| path.as_slice() < &split_path[..]));
+        assert!(other
+            .iter()
+            .all(|(path, _)| path.as_slice() >= &split_path[..]));
+
+        let mut union = tree.to_btreemap();
+        union.extend(other.to_btreemap());
+        assert_eq!(union, original);
+
+        assert_eq!(tree.compute_hash(), left.compute_hash());
+        assert_eq!(other.compute_hash(), right.compute_hash());
+    }
+
+    #[test]
+    fn split_off_within_extension() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for path in [&[0x12, 0x34, 0x56][..], &[0x12, 0x34, 0x78]] {
+            tree.insert(path, path);
+        }
+        assert!(matches!(&tree.nodes[*tree.root_ref], Node::Extension(_)));
+
+        let mut other = tree.clone();
+        let mut right = other.split_off(&[0x12, 0x30]);
+        assert!(other.is_empty());
+        assert_eq!(right.compute_hash(), tree.compute_hash());
+
+        let mut right = tree.split_off(&[0x12, 0x34, 0x60]);
+        assert_eq!(
+            tree.to_sorted_vec(),
+            [(vec![0x12, 0x34, 0x56], &[0x12, 0x34, 0x56][..])]
+        );
+        assert_eq!(
+            right.to_sorted_vec(),
+            [(vec![0x12, 0x34, 0x78], &[0x12, 0x34, 0x78][..])]
+        );
+        assert!(right.split_off(&[0xFF]).is_empty());
+    }
+
+    #[test]
+    fn insert_bounded() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.insert_bounded(&[0x12, 0x34], &[0x01], 1), Ok(None));
+        assert_eq!(
+            tree.insert_bounded(&[0x12, 0x34], &[0x02], 1),
+            Ok(Some(&[0x01][..]))
+        );
+
+        // An extension, a branch and another leaf are needed.
+        let root_hash = tree.compute_hash();
+        assert_eq!(
+            tree.insert_bounded(&[0x12, 0x35], &[0x03], 3),
+            Err(NodeBudgetExceeded {
+                max_nodes: 3,
+                required: 4,
+            }),
+        );
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.get(&&[0x12, 0x35][..]), None);
+        assert_eq!(tree.compute_hash(), root_hash);
+
+        assert_eq!(tree.insert_bounded(&[0x12, 0x35], &[0x03], 4), Ok(None));
+        assert_eq!(tree.nodes.len(), 4);
+    }
+
+    #[test]
+    fn insert_bounded_node_count() {
+        // Every kind of split, checked against the actual node count in `insert_bounded`.
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for i in 0..2000u32 {
+            let bytes = Keccak256::digest(i.to_be_bytes());
+            let path = bytes[1..(bytes[0] % 6) as usize + 1].to_vec();
+            tree.insert_bounded(path.clone(), path, usize::MAX).unwrap();
+        }
+    }
+
+    #[test]
+    fn get_fixed() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        assert_eq!(tree.get_fixed(&[0x12; 4]), None);
+
+        let paths = (0..500u32)
+            .map(|i| Keccak256::digest(i.to_be_bytes())[..4].to_vec())
+            .collect::<Vec<_>>();
+        for path in &paths {
+            tree.insert(path.clone(), path.clone());
+        }
+        // Shorter and longer paths, so branches have values and leaves are past the queries.
+        tree.insert(paths[0][..2].to_vec(), vec![0x01]);
+        tree.insert([paths[1].as_slice(), &[0x00]].concat(), vec![0x02]);
+
+        for i in 500..1000u32 {
+            let path = Keccak256::digest(i.to_be_bytes());
+            let path: [u8; 4] = path[..4].try_into().unwrap();
+            assert_eq!(tree.get_fixed(&path), tree.get_bytes(&path));
+        }
+        for path in &paths {
+            let path: [u8; 4] = path.as_slice().try_into().unwrap();
+            assert_eq!(tree.get_fixed(&path), Some(&path.to_vec()));
+        }
+
+        let path: [u8; 2] = paths[0][..2].try_into().unwrap();
+        assert_eq!(tree.get_fixed(&path), Some(&vec![0x01]));
+        assert_eq!(
+            tree.get_fixed(&[paths[0][0]]),
+            tree.get_bytes(&paths[0][..1])
+        );
+        assert_eq!(tree.get_fixed(&[]), None);
+    }
+
+    #[test]
+    fn try_insert_batch() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(&[0x12, 0x34], &[0x01]);
+        let root_hash = tree.compute_hash();
+
+        // The third entry splits the extension in two around a new branch, adding three nodes to
+        // the previous five.
+        let entries = [
+            (&[0x12, 0x35][..], &[0x02][..]),
+            (&[0x12, 0x36], &[0x03]),
+            (&[0x10], &[0x04]),
+            (&[0x12, 0x34], &[0x05]),
+            (&[0x20], &[0x06]),
+        ];
+        assert_eq!(
+            tree.try_insert_batch(entries, 7),
+            Err((
+                2,
+                NodeBudgetExceeded {
+                    max_nodes: 7,
+                    required: 8,
+                },
+            )),
+        );
+        assert_eq!(tree.to_sorted_vec(), [(vec![0x12, 0x34], &[0x01][..])]);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.compute_hash(), root_hash);
+
+        assert_eq!(tree.try_insert_batch(entries, usize::MAX), Ok(()));
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.get(&&[0x12, 0x34][..]), Some(&&[0x05][..]));
+
+        // Replaced values and repeated paths are undone as well.
+        let sorted_vec = tree.to_sorted_vec();
+        let root_hash = tree.compute_hash();
+        let node_count = tree.nodes.len();
+        let entries = [
+            (&[0x12, 0x34][..], &[0x07][..]),
+            (&[0x30], &[0x08]),
+            (&[0x30], &[0x09]),
+            (&[0x12, 0x34], &[0x0A]),
+            (&[0x40], &[0x0B]),
+            (&[0x50], &[0x0C]),
+        ];
+        assert_eq!(
+            tree.try_insert_batch(entries, node_count + 2),
+            Err((
+                5,
+                NodeBudgetExceeded {
+                    max_nodes: node_count + 2,
+                    required: node_count + 3,
+                },
+            )),
+        );
+        assert_eq!(tree.to_sorted_vec(), sorted_vec);
+        assert_eq!(tree.nodes.len(), node_count);
+        assert_eq!(tree.compute_hash(), root_hash);
+    }
+
+    #[test]
+    fn get_proof() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert!(tree.get_proof(b"first").is_empty());
+
+        for (path, value) in [
+            (&b"first"[..], &[0xAA; 40][..]),
+            (b"second", &[0xBB; 40]),
+            (b"secret", &[0xCC; 2]),
+            (b"secrets", &[0xDD; 2]),
+        ] {
+            tree.insert(path, value);
+        }
+        let root_hash = tree.compute_hash();
+
+        for path in [
+            &b"first"[..],
+            b"second",
+            b"secret",
+            b"secrets",
+            b"third",
+            b"A",
+        ] {
+            let proof = tree.get_proof(path);
+            assert_eq!(
+                Keccak256::digest(&proof[0]).as_slice(),
+                root_hash.as_slice()
+            );
+            assert_eq!(
+                proof.iter().map(Vec::len).sum::<usize>(),
+                tree.proof_size_estimate(path),
+            );
+
+            // Every node is referenced from its parent's encoding, either by hash or inlined.
+            for pair in proof.windows(2) {
+                let (parent, child) = (&pair[0], &pair[1]);
+                let child_ref = if child.len() < 32 {
+                    child.clone()
+                } else {
+                    Keccak256::digest(child).to_vec()
+                };
+                assert!(parent.windows(child_ref.len()).any(|x| x == child_ref));
+            }
+        }
+
+        // The proof of an existing path ends at the node holding its value.
+        let proof = tree.get_proof(b"secrets");
+        assert!(proof.last().unwrap().windows(2).any(|x| x == [0xDD; 2]));
+        assert!(proof.iter().any(|x| x.len() < 32));
+    }
+
+    #[test]
+    fn get_with_proof() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.get_with_proof(b"first"), (None, Vec::new()));
+
+        for path in [&b"first"[..], b"second", b"sec", b"secret", b"secrets"] {
+            tree.insert(path, path);
+        }
+
+        for path in [&b"first"[..], b"second", b"sec", b"secret", b"secrets"] {
+            assert_eq!(
+                tree.get_with_proof(path),
+                (tree.get(&path), tree.get_proof(path)),
+            );
+        }
+        for path in [&b"firs"[..], b"se", b"secre", b"secretss", b"third", b""] {
+            assert_eq!(tree.get_with_proof(path), (None, tree.get_proof(path)));
+        }
+    }
+
+    thread_local! {
+        static DIGEST_COUNT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Keccak-256 counting the digests it finalizes on the current thread.
+    #[derive(Clone, Debug, Default)]
+    struct CountingKeccak256(Keccak256);
+
+    impl HashMarker for CountingKeccak256 {}
+
+    impl OutputSizeUser for CountingKeccak256 {
+        type OutputSize = <Keccak256 as OutputSizeUser>::OutputSize;
+    }
+
+    impl Update for CountingKeccak256 {
+        fn update(&mut self, data: &[u8]) {
+            Update::update(&mut self.0, data);
+        }
+    }
+
+    impl FixedOutput for CountingKeccak256 {
+        fn finalize_into(self, out: &mut Output<Self>) {
+            DIGEST_COUNT.with(|x| x.set(x.get() + 1));
+            FixedOutput::finalize_into(self.0, out);
+        }
+    }
+
+    #[test]
+    fn get_proof_reuses_cached_hashes() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, CountingKeccak256>::new();
+        for i in 0..256u32 {
+            tree.insert(i.to_be_bytes().to_vec(), vec![0xAA; 40]);
+        }
+
+        let path = 0x7Fu32.to_be_bytes();
+        let digest_count = DIGEST_COUNT.with(Cell::get);
+        let proof = tree.get_proof(&path);
+        assert!(DIGEST_COUNT.with(Cell::get) > digest_count);
+
+        // The hashes computed by the first proof are cached.
+        let digest_count = DIGEST_COUNT.with(Cell::get);
+        assert_eq!(tree.get_proof(&path), proof);
+        assert_eq!(DIGEST_COUNT.with(Cell::get), digest_count);
+
+        // So are the ones computed by compute_hash().
+        tree.compute_hash();
+        let digest_count = DIGEST_COUNT.with(Cell::get);
+        let other_proof = tree.get_proof(&0xF0u32.to_be_bytes());
+        assert_eq!(DIGEST_COUNT.with(Cell::get), digest_count);
+        assert_eq!(other_proof[0], proof[0]);
+    }
+
+    #[test]
+    fn proof_size_estimate() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.proof_size_estimate(b"first"), 0);
+
+        tree.insert(b"first", &[0xAA; 40]);
+        let mut encoding = Vec::new();
+        assert!(tree.encode_node_into(tree.root_ref(), 0, &mut encoding));
+        assert_eq!(tree.proof_size_estimate(b"first"), encoding.len());
+        assert_eq!(tree.proof_size_estimate(b"second"), encoding.len());
+
+        // The root branch, then the leaf for `second`, which also proves `third` is absent. Paths
+        // whose slot is empty stop at the branch.
+        tree.insert(b"second", &[0xBB; 40]);
+        let leaf_ref = match &tree.nodes[*tree.root_ref] {
+            Node::Branch(branch_node) => branch_node.choices[7],
+            _ => panic!("expected a branch node"),
+        };
+        let mut encoding = Vec::new();
+        assert!(tree.encode_node_into(tree.root_ref(), 0, &mut encoding));
+        let root_len = encoding.len();
+        assert!(tree.encode_node_into(leaf_ref, 1, &mut encoding));
+        assert_eq!(tree.proof_size_estimate(b"second"), encoding.len());
+        assert_eq!(tree.proof_size_estimate(b"third"), encoding.len());
+        assert_eq!(tree.proof_size_estimate(b"A"), root_len);
+    }
+
+    #[test]
+    fn metadata() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        let mut metadata = MetadataStorage::new();
+
+        assert_eq!(
+            tree.insert_with_meta(&mut metadata, b"first", b"a", 1),
+            None
+        );
+        assert_eq!(
+            tree.insert_with_meta(&mut metadata, b"second", b"b", 2),
+            None
+        );
+        tree.insert(b"third", b"c");
+
+        assert_eq!(tree.get_meta(&metadata, &&b"first"[..]), Some(&1));
+        assert_eq!(tree.get_meta(&metadata, &&b"third"[..]), None);
+        assert_eq!(tree.get_meta(&metadata, &&b"fourth"[..]), None);
+
+        let hash = tree.compute_hash();
+        assert_eq!(tree.set_meta(&mut metadata, &&b"first"[..], 3), Some(1));
+        assert_eq!(tree.set_meta(&mut metadata, &&b"third"[..], 4), None);
+        assert_eq!(tree.set_meta(&mut metadata, &&b"fourth"[..], 5), None);
+        assert_eq!(
+            tree.insert_with_meta(&mut metadata, b"second", b"b", 6),
+            Some(&b"b"[..]),
+        );
+        assert_eq!(tree.peek_root(), Some(hash));
+
+        assert_eq!(tree.get_meta(&metadata, &&b"first"[..]), Some(&3));
+        assert_eq!(tree.get_meta(&metadata, &&b"second"[..]), Some(&6));
+        assert_eq!(tree.get_meta(&metadata, &&b"third"[..]), Some(&4));
+        assert_eq!(tree.get_meta(&metadata, &&b"fourth"[..]), None);
     }
 
-    /// Retrieve a value from the tree given its path.
-    pub fn get(&self, path: &P) -> Option<&V> {
-        self.nodes.get(*self.root_ref).and_then(|root_node| {
-            root_node.get(&self.nodes, &self.values, NibbleSlice::new(path.as_ref()))
-        })
+    #[test]
+    fn explain_absence() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.explain_absence(&&[0x12][..]), Some(Absence::EmptyTree),);
+
+        // extension { [1, 2], branch { 3 => leaf, 4 => extension { [5], branch { .. } } } }
+        for path in [&[0x12, 0x30][..], &[0x12, 0x45, 0x60], &[0x12, 0x45, 0x70]] {
+            tree.insert(path, path);
+        }
+
+        assert_eq!(tree.explain_absence(&&[0x12, 0x30][..]), None);
+        assert_eq!(tree.explain_absence(&&[0x12, 0x45, 0x60][..]), None);
+        assert_eq!(
+            tree.explain_absence(&&[0x13][..]),
+            Some(Absence::ExtensionMismatch { at_depth: 0 }),
+        );
+        assert_eq!(
+            tree.explain_absence(&&[0x12, 0x50][..]),
+            Some(Absence::EmptyBranchSlot {
+                at_depth: 2,
+                nibble: 5,
+            }),
+        );
+        assert_eq!(
+            tree.explain_absence(&&[0x12, 0x31][..]),
+            Some(Absence::LeafPathMismatch { at_depth: 3 }),
+        );
+        assert_eq!(
+            tree.explain_absence(&&[0x12, 0x46][..]),
+            Some(Absence::ExtensionMismatch { at_depth: 3 }),
+        );
+        assert_eq!(
+            tree.explain_absence(&&[0x12, 0x45][..]),
+            Some(Absence::MissingBranchValue { at_depth: 4 }),
+        );
     }
 
-    /// Insert a value into the tree.
-    pub fn insert(&mut self, path: P, value: V) -> Option<V> {
-        // Mark hash as dirty.
-        self.hash.0 = false;
+    #[test]
+    fn get_branch_value_prefix_only() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(&[0x12], b"a");
+        tree.insert(&[0x12, 0x34], b"b");
 
-        match self.nodes.try_remove(*self.root_ref) {
-            Some(root_node) => {
-                // If the tree is not empty, call the root node's insertion logic.
-                let (root_node, insert_action) = root_node.insert(
-                    &mut self.nodes,
-                    &mut self.values,
-                    NibbleSlice::new(path.as_ref()),
-                );
-                self.root_ref = NodeRef::new(self.nodes.insert(root_node));
+        // The branch's value must not be returned for longer paths without a matching choice.
+        assert_eq!(tree.get(&&[0x12, 0x99][..]), None);
 
-                match insert_action.quantize_self(self.root_ref) {
-                    InsertAction::Insert(node_ref) => {
-                        let value_ref = ValueRef::new(self.values.insert((path, value)));
-                        match self
-                            .nodes
-                            .get_mut(*node_ref)
-                            .expect("inconsistent internal tree structure")
-                        {
-                            Node::Leaf(leaf_node) => leaf_node.update_value_ref(value_ref),
-                            Node::Branch(branch_node) => branch_node.update_value_ref(value_ref),
-                            _ => panic!("inconsistent internal tree structure"),
-                        };
+        tree.insert(&[0x12, 0x99], b"a");
+        assert_eq!(tree.get(&&[0x12, 0x99][..]), Some(&&b"a"[..]));
+    }
 
-                        None
-                    }
-                    InsertAction::Replace(value_ref) => {
-                        let (_, old_value) = self
-                            .values
-                            .get_mut(*value_ref)
-                            .expect("inconsistent internal tree structure");
+    #[test]
+    fn prefix_histogram() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for path in [
+            &[0x12][..],
+            &[0x12, 0x34],
+            &[0x13],
+            &[0x1F, 0x00],
+            &[0x1F, 0x01],
+            &[0x1F, 0x02],
+            &[0xA0, 0x00, 0x01],
+        ] {
+            tree.insert(path, b"value");
+        }
+
+        assert_eq!(
+            tree.prefix_histogram(2),
+            BTreeMap::from([
+                (vec![0x1, 0x2], 2),
+                (vec![0x1, 0x3], 1),
+                (vec![0x1, 0xF], 3),
+                (vec![0xA, 0x0], 1),
+            ]),
+        );
+        assert_eq!(
+            tree.prefix_histogram(3),
+            BTreeMap::from([
+                (vec![0x1, 0x2], 1),
+                (vec![0x1, 0x2, 0x3], 1),
+                (vec![0x1, 0x3], 1),
+                (vec![0x1, 0xF, 0x0], 3),
+                (vec![0xA, 0x0, 0x0], 1),
+            ]),
+        );
+        assert_eq!(tree.prefix_histogram(0), BTreeMap::from([(vec![], 7)]));
+    }
 
-                        Some(replace(old_value, value))
+    #[test]
+    fn apply_staged() {
+        let staging = StagingBuffer::new();
+        std::thread::scope(|scope| {
+            for thread_index in 0..4u8 {
+                let staging = &staging;
+                scope.spawn(move || {
+                    for index in 0..64u8 {
+                        staging.push(vec![thread_index, index], vec![thread_index ^ index]);
                     }
-                    _ => unreachable!(),
-                }
+                });
             }
-            None => {
-                // If the tree is empty, just add a leaf.
-                let value_ref = ValueRef::new(self.values.insert((path, value)));
-                self.root_ref = NodeRef::new(self.nodes.insert(LeafNode::new(value_ref).into()));
+        });
+        staging.push(vec![0, 0], vec![0xFF]);
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        tree.apply_staged(staging);
 
-                None
+        let mut expected_tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for thread_index in 0..4u8 {
+            for index in 0..64u8 {
+                expected_tree.insert(vec![thread_index, index], vec![thread_index ^ index]);
             }
         }
+        expected_tree.insert(vec![0, 0], vec![0xFF]);
+
+        assert_eq!(tree.len(), 256);
+        assert_eq!(tree.compute_hash(), expected_tree.compute_hash());
     }
 
-    /// Return the root hash of the tree (or recompute if needed).
-    pub fn compute_hash(&mut self) -> &Output<H> {
-        if self.hash.0 {
-            &self.hash.1
-        } else {
-            if self.root_ref.is_valid() {
-                let root_node = self
-                    .nodes
-                    .get(*self.root_ref)
-                    .expect("inconsistent internal tree structure");
+    #[test]
+    fn value_len() {
+        let mut tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        tree.insert(b"first", vec![0xAA; 40]);
+        tree.insert(b"second", vec![]);
+
+        for path in [&b"first"[..], b"second", b"third"] {
+            assert_eq!(
+                tree.value_len(&path),
+                tree.get(&path).map(|x| x.as_slice().len()),
+            );
+        }
+        assert_eq!(tree.value_len(&&b"first"[..]), Some(40));
+        assert_eq!(tree.value_len(&&b"third"[..]), None);
+    }
 
-                match root_node.compute_hash(&self.nodes, &self.values, 0) {
-                    NodeHashRef::Inline(x) => {
-                        H::new().chain_update(&*x).finalize_into(&mut self.hash.1)
-                    }
-                    NodeHashRef::Hashed(x) => self.hash.1.copy_from_slice(&x),
-                }
+    #[test]
+    fn raw_values() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        tree.insert(b"first", b"a");
+        tree.insert(b"second", b"b");
+        tree.insert(b"third", b"c");
+        tree.insert(b"second", b"d");
+
+        let mut values = tree.raw_values().copied().collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, [b"a", b"c", b"d"]);
+    }
 
-                self.hash.0 = true;
-            } else {
-                H::new()
-                    .chain_update([0x80])
-                    .finalize_into(&mut self.hash.1);
+    #[test]
+    fn fold() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.fold(0, |acc, _, value| acc + value.len()), 0);
 
-                self.hash.0 = true;
-            }
+        tree.insert(b"first", b"a");
+        tree.insert(b"second", b"bc");
+        tree.insert(b"secondary", b"def");
+        tree.insert(b"third", b"");
+
+        assert_eq!(
+            tree.fold(0, |acc, _, value| acc + value.len()),
+            tree.iter().map(|(_, value)| value.len()).sum::<usize>(),
+        );
+        assert_eq!(
+            tree.fold(Vec::new(), |mut acc, path, _| {
+                acc.push(path.to_vec());
+                acc
+            }),
+            tree.iter()
+                .map(|(path, _)| path.to_vec())
+                .collect::<Vec<_>>(),
+        );
+    }
 
-            &self.hash.1
+    #[test]
+    fn branch_values() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.branch_values().count(), 0);
+
+        for path in [
+            &[0x12][..],
+            &[0x12, 0x34],
+            &[0x12, 0x34, 0x56],
+            &[0x12, 0x78],
+            &[0x9A],
+            &[0x9A, 0xBC, 0xDE],
+        ] {
+            tree.insert(path, path);
         }
+
+        let mut branch_values = tree.branch_values().collect::<Vec<_>>();
+        branch_values.sort();
+        assert_eq!(
+            branch_values,
+            [
+                (vec![0x12], &&[0x12][..]),
+                (vec![0x12, 0x34], &&[0x12, 0x34][..]),
+                (vec![0x9A], &&[0x9A][..]),
+            ],
+        );
     }
 
-    /// Calculate approximated memory usage (both used and allocated).
-    pub fn memory_usage(&self) -> (usize, usize) {
-        let mem_consumed = size_of::<Node<P, V, H>>() * self.nodes.len()
-            + size_of::<(P, Output<H>, V)>() * self.values.len();
-        let mem_reserved = size_of::<Node<P, V, H>>() * self.nodes.capacity()
-            + size_of::<(P, Output<H>, V)>() * self.values.capacity();
+    #[test]
+    fn depth_distribution() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.depth_distribution(), []);
+
+        tree.insert(&[0x12, 0x34], b"value");
+        assert_eq!(tree.depth_distribution(), [1]);
+
+        for path in [
+            &[0x12, 0x35][..],
+            &[0x12, 0x36, 0x00],
+            &[0x12, 0x36, 0x01],
+            &[0x12],
+            &[0x80],
+        ] {
+            tree.insert(path, b"value");
+        }
 
-        (mem_consumed, mem_reserved)
+        // Depth 1: 0x80. Depth 4: 0x1234, 0x1235. Depth 6: 0x123600, 0x123601. The value at 0x12
+        // is stored in a branch.
+        assert_eq!(tree.depth_distribution(), [0, 1, 0, 0, 2, 0, 2]);
     }
 
-    /// Use after a `.clone()` to reserve the capacity the slabs would have if they hadn't been
-    /// cloned.
-    ///
-    /// Note: Used by the benchmark to mimic real conditions.
-    #[doc(hidden)]
-    pub fn reserve_next_power_of_two(&mut self) {
-        self.nodes
-            .reserve(self.nodes.capacity().next_power_of_two());
-        self.values
-            .reserve(self.values.capacity().next_power_of_two());
+    #[test]
+    fn rehash_into() {
+        let data = (0..100u8)
+            .map(|x| (vec![x.wrapping_mul(73), x], vec![x; 40]))
+            .collect::<Vec<_>>();
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        let mut expected_tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Sha3_256>::new();
+        for (path, value) in data.iter().cloned() {
+            tree.insert(path.clone(), value.clone());
+            expected_tree.insert(path, value);
+        }
+        tree.compute_hash();
+
+        let mut tree = tree.rehash_into::<Sha3_256>();
+        assert_eq!(tree.peek_root(), None);
+        for (path, value) in &data {
+            assert_eq!(tree.get(path), Some(value));
+        }
+        assert_eq!(tree.compute_hash(), expected_tree.compute_hash());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::sync::Arc;
+    #[test]
+    fn map_values() {
+        let data = (0..100u8)
+            .map(|x| (vec![x.wrapping_mul(73), x], vec![x; 4]))
+            .collect::<Vec<_>>();
 
-    use crate::*;
-    use hex_literal::hex;
-    use proptest::collection::{btree_set, vec};
-    use proptest::prelude::*;
-    use sha3::Keccak256;
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        let mut expected_tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for (path, value) in data.iter().cloned() {
+            tree.insert(path.clone(), value.clone());
+            expected_tree.insert(path, value.repeat(10));
+        }
+
+        let mut mapped_tree = tree.map_values(|value| value.repeat(10));
+        assert_eq!(mapped_tree.peek_root(), None);
+        assert!(mapped_tree
+            .iter()
+            .map(|(path, _)| path)
+            .eq(tree.iter().map(|(path, _)| path)));
+        for (path, value) in &data {
+            assert_eq!(mapped_tree.get(path), Some(&value.repeat(10)));
+        }
+        assert_eq!(mapped_tree.compute_hash(), expected_tree.compute_hash());
+        assert_ne!(mapped_tree.compute_hash(), tree.compute_hash());
+    }
 
     #[test]
-    fn compute_hash() {
+    fn peek_root() {
         let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        assert_eq!(tree.peek_root(), None);
 
         tree.insert(b"first", b"value");
-        tree.insert(b"second", b"value");
+        assert_eq!(tree.peek_root(), None);
 
-        assert_eq!(
-            tree.compute_hash().as_slice(),
-            hex!("f7537e7f4b313c426440b7fface6bff76f51b3eb0d127356efbe6f2b3c891501"),
-        );
+        let hash = tree.compute_hash();
+        assert_eq!(tree.peek_root(), Some(hash));
+
+        tree.insert(b"second", b"value");
+        assert_eq!(tree.peek_root(), None);
     }
 
     #[test]
-    fn compute_hash_long() {
+    fn encode_node_into() {
         let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
 
         tree.insert(b"first", b"value");
         tree.insert(b"second", b"value");
-        tree.insert(b"third", b"value");
-        tree.insert(b"fourth", b"value");
 
+        let mut buffer = Vec::new();
+        assert!(tree.encode_node_into(tree.root_ref(), 0, &mut buffer));
         assert_eq!(
+            Keccak256::digest(&buffer).as_slice(),
             tree.compute_hash().as_slice(),
-            hex!("e2ff76eca34a96b68e6871c74f2a5d9db58e59f82073276866fdd25e560cedea"),
         );
+
+        let root_node = match &tree.nodes[*tree.root_ref] {
+            Node::Branch(branch_node) => branch_node,
+            _ => panic!("expected a branch node"),
+        };
+        for choice in root_node.choices.iter().filter(|x| x.is_valid()) {
+            buffer.clear();
+            assert!(tree.encode_node_into(*choice, 1, &mut buffer));
+            assert_eq!(
+                buffer.as_slice(),
+                tree.nodes[**choice]
+                    .compute_hash(&tree.nodes, &tree.values, 1)
+                    .as_ref(),
+            );
+        }
+
+        buffer.clear();
+        assert!(!tree.encode_node_into(NodeRef::new(10), 0, &mut buffer));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn compute_hash_encodable_values() {
+        struct Account {
+            nonce: u64,
+            balance: u64,
+        }
+
+        impl Encodable for Account {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.nonce.to_be_bytes());
+                out.extend_from_slice(&self.balance.to_be_bytes());
+            }
+        }
+
+        let mut tree = PatriciaMerkleTree::<&[u8], Account, Keccak256>::new();
+        let mut expected_tree = PatriciaMerkleTree::<&[u8], Vec<u8>, Keccak256>::new();
+        for (path, nonce, balance) in [(b"first", 1, 100), (b"other", 2, 200)] {
+            let value = Account { nonce, balance };
+            expected_tree.insert(path, value.encoded().into_owned());
+            tree.insert(path, value);
+        }
+
+        assert_eq!(tree.get(&&b"first"[..]).map(|x| x.balance), Some(100));
+        assert_eq!(tree.compute_hash(), expected_tree.compute_hash());
     }
 
     #[test]
@@ -229,6 +3154,37 @@ mod test {
         assert!(second.is_some());
     }
 
+    #[test]
+    fn value_by_ref() {
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+
+        tree.insert(b"first", b"value");
+
+        let value_ref = match &tree.nodes[*tree.root_ref] {
+            Node::Leaf(leaf_node) => leaf_node.value_ref,
+            _ => panic!("expected a leaf node"),
+        };
+        assert_eq!(
+            tree.value_by_ref(value_ref),
+            Some((&b"first"[..], &&b"value"[..])),
+        );
+        assert_eq!(tree.value_by_ref(ValueRef::new(1)), None);
+    }
+
+    #[test]
+    fn byte_trie_str_paths() {
+        let mut tree = ByteTrie::<&str, Keccak256>::new();
+
+        assert_eq!(tree.insert_str("first", "value1"), None);
+        assert_eq!(tree.insert_str("second", "value2"), None);
+        assert_eq!(tree.insert_bytes(b"first", "value3"), Some("value1"));
+
+        assert_eq!(tree.get_str("first"), Some(&"value3"));
+        assert_eq!(tree.get_str("second"), Some(&"value2"));
+        assert_eq!(tree.get_bytes(b"second"), Some(&"value2"));
+        assert_eq!(tree.get_str("third"), None);
+    }
+
     #[test]
     fn get_inserted_zero() {
         let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
@@ -267,6 +3223,33 @@ mod test {
                 prop_assert_eq!(item.unwrap(), value);
             }
         }
+
+        #[test]
+        fn proptest_remove_matches_fresh_tree(
+            paths in btree_set(vec(any::<u8>(), 0..8), 1..100),
+            removed_mask in vec(any::<bool>(), 100),
+        ) {
+            let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+            let mut expected = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+            for path in &paths {
+                tree.insert(path.clone(), path.clone());
+            }
+            tree.compute_hash();
+
+            for (path, removed) in paths.iter().zip(&removed_mask) {
+                if *removed {
+                    prop_assert_eq!(tree.remove(path), Some(path.clone()));
+                    prop_assert_eq!(tree.remove(path), None);
+                } else {
+                    expected.insert(path.clone(), path.clone());
+                }
+            }
+
+            prop_assert_eq!(tree.len(), expected.len());
+            prop_assert_eq!(tree.nodes.len(), expected.nodes.len());
+            prop_assert_eq!(tree.to_sorted_vec(), expected.to_sorted_vec());
+            prop_assert_eq!(tree.compute_hash(), expected.compute_hash());
+        }
     }
 
     #[test]
@@ -413,6 +3396,174 @@ mod test {
         assert_eq!(tree.get(&vec![0x1A]), Some(&vec![0x1A]));
     }
 
+    #[test]
+    fn compute_hash_insertion_order_independent() {
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+
+        // Short paths make prefix relations (and therefore branches with values) likely.
+        let mut data = BTreeMap::new();
+        while data.len() < 500 {
+            let mut path = vec![0; rng.gen_range(1..=4)];
+            rng.fill_bytes(&mut path);
+            let mut value = vec![0; rng.gen_range(1..=40)];
+            rng.fill_bytes(&mut value);
+
+            data.insert(path, value);
+        }
+        let mut data: Vec<_> = data.into_iter().collect();
+
+        let expected_hash = compute_hash_ours(data.clone());
+        for _ in 0..8 {
+            data.shuffle(&mut rng);
+            assert_eq!(compute_hash_ours(data.clone()), expected_hash);
+        }
+        assert_eq!(compute_hash_cita_trie(data), expected_hash);
+    }
+
+    #[test]
+    fn get_insert_shorter_than_extension_prefix() {
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+
+        tree.insert(vec![0x00, 0x00, 0x00], vec![0x00, 0x00, 0x00]);
+        tree.insert(vec![0x00, 0x00, 0x01], vec![0x00, 0x00, 0x01]);
+        tree.insert(vec![0x01], vec![0x01]);
+
+        assert_eq!(tree.get(&vec![0x00, 0x00]), None);
+        tree.insert(vec![0x00, 0x00], vec![0x00, 0x00]);
+
+        assert_eq!(tree.get(&vec![0x00, 0x00]), Some(&vec![0x00, 0x00]));
+        assert_eq!(
+            tree.get(&vec![0x00, 0x00, 0x00]),
+            Some(&vec![0x00, 0x00, 0x00])
+        );
+        assert_eq!(
+            tree.get(&vec![0x00, 0x00, 0x01]),
+            Some(&vec![0x00, 0x00, 0x01])
+        );
+        assert_eq!(tree.get(&vec![0x01]), Some(&vec![0x01]));
+    }
+
+    #[test]
+    fn compute_hash_branch_with_value_from_leaf_split() {
+        // The first leaf is split into a branch holding its value when inserting the others.
+        let data = vec![
+            (vec![0x12], vec![0xAB; 60]),
+            (vec![0x12, 0x34], vec![0x01]),
+            (vec![0x12, 0x56], vec![0xCD; 40]),
+        ];
+
+        let mut tree = PatriciaMerkleTree::<Vec<u8>, Vec<u8>, Keccak256>::new();
+        for (path, value) in data.clone() {
+            tree.insert(path, value);
+        }
+        assert!(tree
+            .nodes
+            .iter()
+            .any(|(_, node)| matches!(node, Node::Branch(x) if x.value_ref.is_valid())));
+
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            compute_hash_cita_trie(data).as_slice(),
+        );
+    }
+
+    #[test]
+    fn insert_extension_triples_canonical() {
+        let triples: [[&[u8]; 3]; 6] = [
+            [&[0x12, 0x34], &[0x12, 0x35], &[0x12]],
+            [&[0x12, 0x34, 0x56], &[0x12, 0x34, 0x57], &[0x12, 0x38]],
+            [
+                &[0x12, 0x34],
+                &[0x12, 0x34, 0x56],
+                &[0x12, 0x34, 0x56, 0x78],
+            ],
+            [&[0x01, 0x23, 0x45], &[0x01, 0x23, 0x46], &[0x01, 0x33]],
+            [&[0x00], &[0x00, 0x00], &[0x00, 0x00, 0x00]],
+            [&[0xAB, 0xCD, 0xEF], &[0xAB, 0xCD, 0xE0], &[0xAB, 0xC0]],
+        ];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+
+        for triple in triples {
+            let data = triple
+                .iter()
+                .map(|path| (path.to_vec(), path.to_vec()))
+                .collect::<Vec<_>>();
+            let expected_hash = compute_hash_cita_trie(data);
+
+            for order in orders {
+                let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+                for index in order {
+                    tree.insert(triple[index], triple[index]);
+                }
+
+                assert_eq!(
+                    tree.integrity_report(),
+                    [],
+                    "{triple:02x?} in order {order:?}"
+                );
+                assert_eq!(
+                    tree.compute_hash().as_slice(),
+                    expected_hash.as_slice(),
+                    "{triple:02x?} in order {order:?}",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn insert_odd_prefix_at_odd_offset() {
+        let paths: [&[u8]; 4] = [
+            &[0x12, 0x40],
+            &[0x12, 0x50],
+            &[0x12, 0x35, 0x67, 0x89],
+            &[0x12, 0x35, 0x67, 0x00],
+        ];
+
+        let mut tree = PatriciaMerkleTree::<&[u8], &[u8], Keccak256>::new();
+        for path in paths {
+            tree.insert(path, path);
+        }
+
+        // The last insertion splits a leaf at offset 3 after an odd (3 nibbles) common prefix.
+        let branch_ref = match &tree.nodes[*tree.root_ref] {
+            Node::Extension(extension_node) => {
+                assert_eq!(extension_node.prefix().collect::<Vec<_>>(), [1, 2]);
+                extension_node.child_ref
+            }
+            _ => panic!("expected an extension node"),
+        };
+        let extension_ref = match &tree.nodes[*branch_ref] {
+            Node::Branch(branch_node) => branch_node.choices[3],
+            _ => panic!("expected a branch node"),
+        };
+        let branch_ref = match &tree.nodes[*extension_ref] {
+            Node::Extension(extension_node) => {
+                assert_eq!(extension_node.prefix().collect::<Vec<_>>(), [5, 6, 7]);
+                extension_node.child_ref
+            }
+            _ => panic!("expected an extension node"),
+        };
+        match &tree.nodes[*branch_ref] {
+            Node::Branch(branch_node) => assert_eq!(branch_node.occupied_mask(), 0x0101),
+            _ => panic!("expected a branch node"),
+        }
+
+        for path in paths {
+            assert_eq!(tree.get(&path), Some(&path));
+        }
+        assert_eq!(
+            tree.compute_hash().as_slice(),
+            compute_hash_cita_trie(paths.iter().map(|x| (x.to_vec(), x.to_vec())).collect()),
+        );
+    }
+
     #[test]
     fn compute_hashes() {
         expect_hash(vec![
@@ -0,0 +1,46 @@
+use crate::ValueRef;
+
+/// Sidecar storage associating metadata with a tree's values, indexed by their value references.
+///
+/// It's kept outside of the tree, therefore the metadata never participates in hashing. Entries are
+/// only replaced or cleared explicitly.
+#[derive(Clone, Debug)]
+pub struct MetadataStorage<M> {
+    entries: Vec<Option<M>>,
+}
+
+impl<M> MetadataStorage<M> {
+    /// Create an empty metadata storage.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Retrieve the metadata of a value reference.
+    pub fn get(&self, value_ref: ValueRef) -> Option<&M> {
+        self.entries.get(*value_ref)?.as_ref()
+    }
+
+    /// Set the metadata of a value reference, returning the previous one.
+    pub fn set(&mut self, value_ref: ValueRef, meta: M) -> Option<M> {
+        assert!(value_ref.is_valid());
+
+        if *value_ref >= self.entries.len() {
+            self.entries.resize_with(*value_ref + 1, || None);
+        }
+
+        self.entries[*value_ref].replace(meta)
+    }
+
+    /// Clear the metadata of a value reference, returning it.
+    pub fn clear(&mut self, value_ref: ValueRef) -> Option<M> {
+        self.entries.get_mut(*value_ref)?.take()
+    }
+}
+
+impl<M> Default for MetadataStorage<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
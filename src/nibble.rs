@@ -72,25 +72,60 @@ impl<'a> NibbleSlice<'a> {
         }
     }
 
+    /// Create a slice already advanced by `offset` nibbles.
+    pub fn new_offset(inner: &'a [u8], offset: usize) -> Self {
+        debug_assert!(
+            offset <= 2 * inner.len(),
+            "offset past the end of the slice"
+        );
+
+        Self {
+            data: inner,
+            offset,
+        }
+    }
+
     pub fn len(&self) -> usize {
-        2 * self.data.len() - self.offset
+        (2 * self.data.len()).saturating_sub(self.offset)
     }
 
     pub fn offset(&self) -> usize {
         self.offset
     }
 
+    /// Return the nibble `index` positions after the offset without advancing, or `None` if it's
+    /// past the end.
+    pub fn nibble_at(&self, index: usize) -> Option<Nibble> {
+        let offset = self.offset.checked_add(index)?;
+        self.data.get(offset >> 1).map(|byte| {
+            let byte = if offset & 0x01 != 0 {
+                byte & 0x0F
+            } else {
+                byte >> 4
+            };
+
+            match Nibble::try_from(byte) {
+                Ok(x) => x,
+                Err(_) => unreachable!(),
+            }
+        })
+    }
+
     pub fn split_to_vec(&self, offset: usize) -> NibbleVec {
+        debug_assert!(offset <= self.len(), "split past the end of the slice");
+
         NibbleVec {
             data: SmallVec::from_slice(
                 &self.data[self.offset >> 1..(self.offset + offset + 1) >> 1],
             ),
-            first_is_half: self.offset % 2 != 0,
-            last_is_half: (self.offset + offset) % 2 != 0,
+            first_is_half: self.offset & 0x01 != 0,
+            last_is_half: (self.offset + offset) & 0x01 != 0,
         }
     }
 
     pub fn offset_add(&mut self, delta: usize) {
+        debug_assert!(delta <= self.len(), "offset past the end of the slice");
+
         self.offset += delta;
     }
 
@@ -101,13 +136,13 @@ impl<'a> NibbleSlice<'a> {
     pub fn skip_prefix(&mut self, prefix: &NibbleVec) -> bool {
         // Check alignment.
         assert_eq!(
-            (self.offset % 2 != 0),
+            (self.offset & 0x01 != 0),
             prefix.first_is_half,
             "inconsistent internal tree structure",
         );
 
         // Prefix can only be a prefix if self.len() >= prefix.len()
-        if self.data.len() < prefix.data.len() {
+        if self.len() < prefix.len() {
             return false;
         }
 
@@ -158,13 +193,19 @@ impl<'a> NibbleSlice<'a> {
 
     /// Compare the rest of the data in self with the data in `other` after the offset in self.
     pub fn cmp_rest(&self, other: &[u8]) -> bool {
-        // Prepare slices.
-        let mut othr_slice = &other[self.offset >> 1..];
-        let mut self_slice = &self.data[self.offset >> 1..];
+        // Prepare slices (a side shorter than the offset can't match).
+        let (mut othr_slice, mut self_slice) = match (
+            other.get(self.offset >> 1..),
+            self.data.get(self.offset >> 1..),
+        ) {
+            (Some(othr_slice), Some(self_slice)) => (othr_slice, self_slice),
+            _ => return false,
+        };
 
-        if self.offset % 2 != 0 {
-            if (othr_slice[0] & 0x0F) != (self_slice[0] & 0x0F) {
-                return false;
+        if self.offset & 0x01 != 0 {
+            match (othr_slice.first(), self_slice.first()) {
+                (Some(a), Some(b)) if (a & 0x0F) == (b & 0x0F) => {}
+                _ => return false,
             }
 
             othr_slice = &othr_slice[1..];
@@ -180,7 +221,7 @@ impl<'a> NibbleSlice<'a> {
         }
 
         // Check alignment and length.
-        assert_eq!(self.offset % 2 != 0, other.first_is_half);
+        assert_eq!(self.offset & 0x01 != 0, other.first_is_half);
 
         // Compare first nibble (if not byte-aligned).
         let mut eq_count = 0;
@@ -230,7 +271,7 @@ impl<'a> NibbleSlice<'a> {
 
         // Check first nibble (if not byte-aligned).
         let mut eq_count = 0;
-        if self.offset % 2 != 0 {
+        if self.offset & 0x01 != 0 {
             if (self.data[self.offset >> 1] & 0x0F) == (other.data[self.offset >> 1] & 0x0F) {
                 eq_count += 1;
             } else {
@@ -267,19 +308,19 @@ impl<'a> Iterator for NibbleSlice<'a> {
     type Item = Nibble;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.data.get(self.offset >> 1).map(|byte| {
-            let byte = if self.offset % 2 != 0 {
-                byte & 0x0F
-            } else {
-                byte >> 4
-            };
+        let nibble = self.nibble_at(0)?;
+        self.offset += 1;
+        Some(nibble)
+    }
 
-            self.offset += 1;
-            match Nibble::try_from(byte) {
-                Ok(x) => x,
-                Err(_) => unreachable!(),
-            }
-        })
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        // Skipping past the end leaves the slice exhausted, as with the default implementation.
+        self.offset = self.offset.saturating_add(n).min(2 * self.data.len());
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
     }
 }
 
@@ -322,6 +363,35 @@ impl NibbleVec {
         }
     }
 
+    /// Build a nibble vector for a prefix which starts `offset` nibbles into a path, so it's
+    /// aligned like the ones sliced from the path itself.
+    pub(crate) fn from_nibbles_at(offset: usize, data_iter: impl Iterator<Item = Nibble>) -> Self {
+        let first_is_half = offset & 0x01 != 0;
+
+        let mut data = SmallVec::new();
+        let mut pos = first_is_half as usize;
+        if first_is_half {
+            data.push(0);
+        }
+        for nibble in data_iter {
+            if pos & 0x01 == 0 {
+                data.push((nibble as u8) << 4);
+            } else {
+                *data.last_mut().unwrap() |= nibble as u8;
+            }
+
+            pos += 1;
+        }
+
+        let mut nibble_vec = Self {
+            data,
+            first_is_half,
+            last_is_half: pos & 0x01 != 0,
+        };
+        nibble_vec.normalize();
+        nibble_vec
+    }
+
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
@@ -330,7 +400,7 @@ impl NibbleVec {
         2 * self.data.len() - self.first_is_half as usize - self.last_is_half as usize
     }
 
-    pub fn iter(&self) -> NibbleVecIter {
+    pub fn iter(&self) -> NibbleVecIter<'_> {
         NibbleVecIter {
             inner: self,
             pos: self.first_is_half as usize,
@@ -342,7 +412,7 @@ impl NibbleVec {
         let mut left_vec = NibbleVec {
             data: SmallVec::from_slice(&self.data[..offset]),
             first_is_half: self.first_is_half,
-            last_is_half: (index + self.first_is_half as usize) % 2 != 0,
+            last_is_half: (index + self.first_is_half as usize) & 0x01 != 0,
         };
         left_vec.normalize();
 
@@ -352,7 +422,7 @@ impl NibbleVec {
             ((offset + self.last_is_half as usize) >> 1) < self.data.len(),
             "out of bounds"
         );
-        let value = if offset % 2 != 0 {
+        let value = if offset & 0x01 != 0 {
             self.data[offset >> 1] & 0x0F
         } else {
             self.data[offset >> 1] >> 4
@@ -369,7 +439,7 @@ impl NibbleVec {
             } else {
                 SmallVec::from_slice(&self.data[offset..])
             },
-            first_is_half: (index + self.first_is_half as usize) % 2 == 0,
+            first_is_half: (index + self.first_is_half as usize) & 0x01 == 0,
             last_is_half: self.last_is_half,
         };
         right_vec.normalize();
@@ -399,13 +469,13 @@ impl<'a> Iterator for NibbleVecIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.data.get(self.pos >> 1).and_then(|byte| {
             if (self.pos >> 1) + 1 == self.inner.data.len()
-                && self.pos % 2 == 1
+                && self.pos & 0x01 != 0
                 && self.inner.last_is_half
             {
                 return None;
             }
 
-            let byte = if self.pos % 2 != 0 {
+            let byte = if self.pos & 0x01 != 0 {
                 byte & 0x0F
             } else {
                 byte >> 4
@@ -420,6 +490,74 @@ impl<'a> Iterator for NibbleVecIter<'a> {
     }
 }
 
+/// Return the number of nibbles in a path.
+pub fn nibble_len(path: &[u8]) -> usize {
+    2 * path.len()
+}
+
+/// Return the maximum depth of a node in a tree whose paths are at most `len` bytes long, where the
+/// root is at depth zero.
+///
+/// Every node but the last one on the way to a value consumes at least a nibble, and a value may be
+/// stored after its path's last nibble, so a proof for such a path has at most one more node than
+/// this.
+pub fn max_depth_for_keylen(len: usize) -> usize {
+    2 * len
+}
+
+/// Render a key as a `0x`-prefixed hexadecimal string, with one digit per nibble.
+pub fn nibbles_to_hex(key: &[u8]) -> String {
+    nibbles_to_hex_prefix(key, nibble_len(key))
+}
+
+/// Render the first `count` nibbles of a key as a `0x`-prefixed hexadecimal string, with one digit
+/// per nibble, so that paths with an odd number of nibbles can be rendered too.
+///
+/// Panics if the key has fewer than `count` nibbles.
+pub fn nibbles_to_hex_prefix(key: &[u8], count: usize) -> String {
+    assert!(
+        count <= nibble_len(key),
+        "nibble count past the end of the key"
+    );
+
+    let mut hex = String::with_capacity(2 + count);
+    hex.push_str("0x");
+    hex.extend(
+        NibbleSlice::new(key)
+            .take(count)
+            .map(|x| char::from_digit(x as u32, 16).unwrap()),
+    );
+
+    hex
+}
+
+/// Parse a key from hexadecimal digits, one per nibble, returning it along with its number of
+/// nibbles.
+///
+/// The `0x` (or `0X`) prefix is optional and whitespace is ignored, so both `0x123` and `1 2 3` are
+/// accepted. When the number of nibbles is odd, the last byte's low nibble is zero. Return `None`
+/// if there's any other character.
+pub fn hex_to_nibbles(hex: &str) -> Option<(Vec<u8>, usize)> {
+    let hex = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+
+    let mut key = Vec::with_capacity(hex.len() / 2 + 1);
+    let mut count = 0;
+    for digit in hex.chars().filter(|x| !x.is_whitespace()) {
+        let nibble = digit.to_digit(16)? as u8;
+        if count & 0x01 == 0 {
+            key.push(nibble << 4);
+        } else {
+            *key.last_mut().unwrap() |= nibble;
+        }
+        count += 1;
+    }
+
+    Some((key, count))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -579,6 +717,20 @@ mod test {
         assert_eq!(slice.offset, 0);
     }
 
+    #[test]
+    fn nibble_slice_skip_prefix_failure_too_short() {
+        let mut slice = NibbleSlice {
+            data: &[0x12, 0x34],
+            offset: 2,
+        };
+        assert!(!slice.skip_prefix(&NibbleVec {
+            data: SmallVec::from_slice(&[0x34, 0x56]),
+            first_is_half: false,
+            last_is_half: false,
+        }));
+        assert_eq!(slice.offset, 2);
+    }
+
     #[test]
     #[should_panic]
     fn nibble_slice_skip_prefix_failure_alignment() {
@@ -975,4 +1127,243 @@ mod test {
         assert_eq!(vec_iter.next(), None);
         assert_eq!(vec_iter.pos, 5);
     }
+
+    #[test]
+    fn nibble_vec_from_nibbles_packed_round_trip() {
+        for count in [0, 1, 2, 63, 64] {
+            let nibbles = (0..count)
+                .map(|x| Nibble::try_from((x % 16) as u8).unwrap())
+                .collect::<Vec<_>>();
+            let vec = NibbleVec::from_nibbles(nibbles.iter().copied());
+
+            assert_eq!(vec.data.len(), (count + 1) >> 1);
+            assert_eq!(vec.len(), count);
+            assert_eq!(vec.iter().collect::<Vec<_>>(), nibbles);
+        }
+    }
+
+    #[test]
+    fn nibble_slice_split_to_vec_packed_round_trip() {
+        let data = (0..32u8).map(|x| x.wrapping_mul(0x9D)).collect::<Vec<_>>();
+
+        for (offset, len) in [(0, 64), (1, 63), (0, 31), (1, 30), (17, 24), (63, 1)] {
+            let slice = NibbleSlice::new_offset(&data, offset);
+            let vec = slice.split_to_vec(len);
+
+            assert_eq!(vec.data.len(), ((offset + len + 1) >> 1) - (offset >> 1));
+            assert_eq!(vec.len(), len);
+            assert_eq!(
+                vec.iter().collect::<Vec<_>>(),
+                slice.take(len).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn nibble_slice_new_offset() {
+        let data = [0x12, 0x34, 0x56];
+
+        for offset in 0..=6 {
+            let mut expected = NibbleSlice::new(&data);
+            expected.offset_add(offset);
+
+            let slice = NibbleSlice::new_offset(&data, offset);
+            assert_eq!(slice.offset(), expected.offset());
+            assert_eq!(slice.len(), expected.len());
+            assert!(slice.eq(expected));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "offset past the end of the slice")]
+    fn nibble_slice_new_offset_past_end() {
+        NibbleSlice::new_offset(&[0x12], 3);
+    }
+
+    #[test]
+    fn nibble_slice_next_at_end() {
+        let mut slice = NibbleSlice::new(&[0x12]);
+        slice.offset_add(2);
+
+        assert_eq!(slice.len(), 0);
+        assert_eq!(slice.next(), None);
+        assert_eq!(slice.offset(), 2);
+    }
+
+    #[test]
+    fn nibble_slice_nth() {
+        let mut slice = NibbleSlice::new(&[0x12, 0x34]);
+
+        assert_eq!(slice.nth(1), Some(Nibble::V2));
+        assert_eq!(slice.nth(1), Some(Nibble::V4));
+        assert_eq!(slice.nth(1), None);
+        assert_eq!(slice.len(), 0);
+    }
+
+    #[test]
+    fn nibble_slice_nth_past_end() {
+        let mut slice = NibbleSlice::new(&[0x12, 0x34]);
+
+        assert_eq!(slice.nth(4), None);
+        assert_eq!(slice.offset(), 4);
+        assert_eq!(slice.next(), None);
+
+        let mut slice = NibbleSlice::new(&[0x12, 0x34]);
+        assert_eq!(slice.nth(usize::MAX), None);
+        assert_eq!(slice.len(), 0);
+    }
+
+    #[test]
+    fn nibble_len() {
+        assert_eq!(super::nibble_len(&[]), 0);
+        assert_eq!(super::nibble_len(&[0x12]), 2);
+        assert_eq!(super::nibble_len(&[0x12; 32]), 64);
+        assert_eq!(
+            super::nibble_len(&[0x12; 32]),
+            NibbleSlice::new(&[0x12; 32]).len()
+        );
+    }
+
+    #[test]
+    fn max_depth_for_keylen_lengths() {
+        assert_eq!(max_depth_for_keylen(0), 0);
+        assert_eq!(max_depth_for_keylen(1), 2);
+        assert_eq!(max_depth_for_keylen(32), 64);
+    }
+
+    #[test]
+    fn nibble_vec_from_nibbles_at_even() {
+        let vec = NibbleVec::from_nibbles_at(2, [Nibble::V1, Nibble::V2, Nibble::V3].into_iter());
+        assert!(vec.iter().eq([Nibble::V1, Nibble::V2, Nibble::V3]));
+        assert_eq!(vec.len(), 3);
+
+        let mut slice = NibbleSlice::new(&[0xFF, 0x12, 0x34]);
+        slice.offset_add(2);
+        assert!(slice.skip_prefix(&vec));
+        assert_eq!(slice.offset(), 5);
+    }
+
+    #[test]
+    fn nibble_vec_from_nibbles_at_odd() {
+        let vec = NibbleVec::from_nibbles_at(3, [Nibble::V1, Nibble::V2].into_iter());
+        assert!(vec.iter().eq([Nibble::V1, Nibble::V2]));
+        assert_eq!(vec.len(), 2);
+
+        let mut slice = NibbleSlice::new(&[0xFF, 0xF1, 0x23]);
+        slice.offset_add(3);
+        assert!(slice.skip_prefix(&vec));
+        assert_eq!(slice.offset(), 5);
+
+        assert!(NibbleVec::from_nibbles_at(3, [].into_iter()).is_empty());
+    }
+
+    #[test]
+    fn nibble_slice_nibble_at() {
+        let slice = NibbleSlice::new(&[0x12, 0x34]);
+        assert_eq!(slice.nibble_at(0), Some(Nibble::V1));
+        assert_eq!(slice.nibble_at(1), Some(Nibble::V2));
+        assert_eq!(slice.nibble_at(3), Some(Nibble::V4));
+
+        let slice = NibbleSlice::new_offset(&[0x12, 0x34], 1);
+        assert_eq!(slice.nibble_at(0), Some(Nibble::V2));
+        assert_eq!(slice.nibble_at(2), Some(Nibble::V4));
+        assert_eq!(slice.offset(), 1);
+    }
+
+    #[test]
+    fn nibble_slice_nibble_at_out_of_range() {
+        let slice = NibbleSlice::new(&[0x12, 0x34]);
+        assert_eq!(slice.nibble_at(4), None);
+        assert_eq!(slice.nibble_at(usize::MAX), None);
+
+        let slice = NibbleSlice::new_offset(&[0x12, 0x34], 3);
+        assert_eq!(slice.nibble_at(1), None);
+        assert_eq!(slice.nibble_at(usize::MAX), None);
+        assert_eq!(NibbleSlice::new(&[]).nibble_at(0), None);
+    }
+
+    #[test]
+    fn nibble_slice_count_prefix_slice_odd_offset() {
+        let mut a = NibbleSlice::new(&[0x12, 0x35, 0x67, 0x89]);
+        let mut b = NibbleSlice::new(&[0x12, 0x35, 0x67, 0x00]);
+        let mut c = NibbleSlice::new(&[0x12, 0x35, 0x67, 0x8A]);
+        let mut d = NibbleSlice::new(&[0x12, 0x36, 0x67, 0x89]);
+        a.offset_add(3);
+        b.offset_add(3);
+        c.offset_add(3);
+        d.offset_add(3);
+
+        assert_eq!(a.count_prefix_slice(&b), 3);
+        assert_eq!(a.count_prefix_slice(&c), 4);
+        assert_eq!(a.count_prefix_slice(&d), 0);
+        assert_eq!(a.count_prefix_slice(&a.clone()), 5);
+    }
+
+    #[test]
+    fn nibble_slice_cmp_rest_other_too_short() {
+        let mut slice = NibbleSlice::new(&[0x12, 0x34]);
+        slice.offset_add(3);
+
+        assert!(!slice.cmp_rest(&[0x12]));
+        assert!(!slice.cmp_rest(&[]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "offset past the end of the slice")]
+    fn nibble_slice_offset_add_past_end() {
+        let mut slice = NibbleSlice::new(&[0x12]);
+        slice.offset_add(3);
+    }
+
+    #[test]
+    fn nibbles_to_hex_even() {
+        assert_eq!(nibbles_to_hex(&[]), "0x");
+        assert_eq!(nibbles_to_hex(&[0x12, 0x34]), "0x1234");
+        assert_eq!(nibbles_to_hex(&[0xAB, 0xCF, 0x00]), "0xabcf00");
+    }
+
+    #[test]
+    fn nibbles_to_hex_odd() {
+        assert_eq!(nibbles_to_hex_prefix(&[0x0F], 1), "0x0");
+        assert_eq!(nibbles_to_hex_prefix(&[0x12, 0xFF], 3), "0x12f");
+        assert_eq!(nibbles_to_hex_prefix(&[0x12, 0xFF], 0), "0x");
+    }
+
+    #[test]
+    #[should_panic(expected = "nibble count past the end of the key")]
+    fn nibbles_to_hex_prefix_past_end() {
+        nibbles_to_hex_prefix(&[0x12], 3);
+    }
+
+    #[test]
+    fn hex_to_nibbles_even() {
+        assert_eq!(hex_to_nibbles(""), Some((vec![], 0)));
+        assert_eq!(hex_to_nibbles("0x1234"), Some((vec![0x12, 0x34], 4)));
+        assert_eq!(hex_to_nibbles("0X1234"), Some((vec![0x12, 0x34], 4)));
+        assert_eq!(hex_to_nibbles("1 2 3 4"), Some((vec![0x12, 0x34], 4)));
+        assert_eq!(hex_to_nibbles("AbcF"), Some((vec![0xAB, 0xCF], 4)));
+        assert_eq!(
+            hex_to_nibbles(&nibbles_to_hex(&[0xFF, 0x00, 0x7E])),
+            Some((vec![0xFF, 0x00, 0x7E], 6)),
+        );
+    }
+
+    #[test]
+    fn hex_to_nibbles_odd() {
+        assert_eq!(hex_to_nibbles("0x12f"), Some((vec![0x12, 0xF0], 3)));
+        assert_eq!(hex_to_nibbles("1 2 f"), Some((vec![0x12, 0xF0], 3)));
+        assert_eq!(
+            hex_to_nibbles(&nibbles_to_hex_prefix(&[0x50, 0x7F], 3)),
+            Some((vec![0x50, 0x70], 3)),
+        );
+    }
+
+    #[test]
+    fn hex_to_nibbles_invalid() {
+        assert_eq!(hex_to_nibbles("0x12g"), None);
+        assert_eq!(hex_to_nibbles("1, 2"), None);
+        assert_eq!(hex_to_nibbles("0x0x12"), None);
+    }
 }
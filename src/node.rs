@@ -1,8 +1,8 @@
 use crate::{
-    hashing::NodeHashRef,
+    hashing::{NodeEncoder, NodeHashRef},
     nibble::NibbleSlice,
     nodes::{BranchNode, ExtensionNode, LeafNode},
-    NodeRef, NodesStorage, ValueRef, ValuesStorage,
+    Encodable, NodeRef, NodesStorage, ValueRef, ValuesStorage,
 };
 use digest::Digest;
 
@@ -16,7 +16,7 @@ use digest::Digest;
 pub enum Node<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     Branch(BranchNode<P, V, H>),
@@ -27,7 +27,7 @@ where
 impl<P, V, H> Node<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub fn get<'a>(
@@ -35,7 +35,7 @@ where
         nodes: &'a NodesStorage<P, V, H>,
         values: &'a ValuesStorage<P, V>,
         path: NibbleSlice,
-    ) -> Option<&V> {
+    ) -> Option<&'a V> {
         match self {
             Node::Branch(branch_node) => branch_node.get(nodes, values, path),
             Node::Extension(extension_node) => extension_node.get(nodes, values, path),
@@ -56,12 +56,56 @@ where
         }
     }
 
+    /// Remove a path's value from the subtree, returning the subtree's new root (if any nodes are
+    /// left) and the reference to the removed value (if it was found).
+    ///
+    /// The value itself is left in the values' storage for the caller to take.
+    pub(crate) fn remove(
+        self,
+        nodes: &mut NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        path: NibbleSlice,
+    ) -> (Option<Self>, Option<ValueRef>) {
+        match self {
+            Node::Branch(branch_node) => branch_node.remove(nodes, values, path),
+            Node::Extension(extension_node) => extension_node.remove(nodes, values, path),
+            Node::Leaf(leaf_node) => leaf_node.remove(values, path),
+        }
+    }
+
+    /// Copy the node using another value type and digest, discarding its cached hash.
+    pub(crate) fn convert<V2, H2>(&self) -> Node<P, V2, H2>
+    where
+        V2: Encodable,
+        H2: Digest,
+    {
+        match self {
+            Node::Branch(branch_node) => {
+                let mut new_node = BranchNode::new(branch_node.choices);
+                new_node.update_value_ref(branch_node.value_ref);
+                new_node.into()
+            }
+            Node::Extension(extension_node) => {
+                ExtensionNode::new(extension_node.prefix.clone(), extension_node.child_ref).into()
+            }
+            Node::Leaf(leaf_node) => LeafNode::new(leaf_node.value_ref).into(),
+        }
+    }
+
+    pub(crate) fn hash(&self) -> &crate::hashing::NodeHash<H> {
+        match self {
+            Node::Branch(branch_node) => &branch_node.hash,
+            Node::Extension(extension_node) => &extension_node.hash,
+            Node::Leaf(leaf_node) => &leaf_node.hash,
+        }
+    }
+
     pub fn compute_hash(
         &self,
         nodes: &NodesStorage<P, V, H>,
         values: &ValuesStorage<P, V>,
         key_offset: usize,
-    ) -> NodeHashRef<H> {
+    ) -> NodeHashRef<'_, H> {
         match self {
             Node::Branch(branch_node) => branch_node.compute_hash(nodes, values, key_offset),
             Node::Extension(extension_node) => {
@@ -70,12 +114,30 @@ where
             Node::Leaf(leaf_node) => leaf_node.compute_hash(nodes, values, key_offset),
         }
     }
+
+    pub(crate) fn encode_into(
+        &self,
+        nodes: &NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        key_offset: usize,
+        encoder: &mut impl NodeEncoder,
+    ) {
+        match self {
+            Node::Branch(branch_node) => {
+                branch_node.encode_into(nodes, values, key_offset, encoder)
+            }
+            Node::Extension(extension_node) => {
+                extension_node.encode_into(nodes, values, key_offset, encoder)
+            }
+            Node::Leaf(leaf_node) => leaf_node.encode_into(nodes, values, key_offset, encoder),
+        }
+    }
 }
 
 impl<P, V, H> From<BranchNode<P, V, H>> for Node<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     fn from(value: BranchNode<P, V, H>) -> Self {
@@ -86,7 +148,7 @@ where
 impl<P, V, H> From<ExtensionNode<P, V, H>> for Node<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     fn from(value: ExtensionNode<P, V, H>) -> Self {
@@ -97,7 +159,7 @@ where
 impl<P, V, H> From<LeafNode<P, V, H>> for Node<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     fn from(value: LeafNode<P, V, H>) -> Self {
@@ -105,20 +167,54 @@ where
     }
 }
 
-/// Returned by .insert() to update the values' storage.
+/// Returned by the nodes' insertion logic to tell the tree what to do with the value.
+///
+/// Nodes never store the value themselves since the tree owns the values' storage. Instead, they
+/// update their structure (creating branches, extensions or leaves as needed) and report where the
+/// value should end up. The tree then either stores the value and links it from the node of an
+/// `Insert`, or swaps it into the slot of a `Replace`.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub(crate) enum InsertAction {
-    /// An insertion is required. The argument points to a node.
+pub enum InsertAction {
+    /// The path is new. The argument points to the leaf or branch which should reference the value
+    /// once stored.
     Insert(NodeRef),
-    /// A replacement is required. The argument points to a value.
+    /// The path already exists. The argument points to the stored value to be replaced.
     Replace(ValueRef),
 
-    /// Special insert where its node_ref is not known.
+    /// Like `Insert`, but for the node returned by the insertion itself.
+    ///
+    /// A node can't know its own reference, since it's only stored (and therefore given one) by its
+    /// parent after the insertion returns. For example, a branch whose own value is being inserted,
+    /// or a leaf replaced by a new branch holding the value, both point to themselves this way.
     InsertSelf,
 }
 
 impl InsertAction {
     /// Replace `Self::InsertSelf` with `Self::Insert(node_ref)`.
+    ///
+    /// Called by the parent once it has stored the returned node under `node_ref`. When a node is
+    /// re-rooted (for example, a branch placed under a new extension), the parent of the new node
+    /// resolves the reference the same way. Other actions are left unchanged, since they already
+    /// point to stored items:
+    ///
+    /// ```
+    /// use patricia_merkle_tree::{InsertAction, NodeRef, ValueRef};
+    ///
+    /// let node_ref = NodeRef::new(3);
+    /// assert_eq!(
+    ///     InsertAction::InsertSelf.quantize_self(node_ref),
+    ///     InsertAction::Insert(node_ref),
+    /// );
+    /// assert_eq!(
+    ///     InsertAction::Insert(NodeRef::new(1)).quantize_self(node_ref),
+    ///     InsertAction::Insert(NodeRef::new(1)),
+    /// );
+    /// assert_eq!(
+    ///     InsertAction::Replace(ValueRef::new(0)).quantize_self(node_ref),
+    ///     InsertAction::Replace(ValueRef::new(0)),
+    /// );
+    /// ```
+    #[must_use]
     pub fn quantize_self(self, node_ref: NodeRef) -> Self {
         match self {
             Self::InsertSelf => Self::Insert(node_ref),
@@ -33,10 +33,10 @@ macro_rules! pmt_node {
         $crate::nodes::BranchNode::<Vec<u8>, _, sha3::Keccak256>::new({
             let mut choices = [$crate::storage::NodeRef::default(); 16];
             $(
-                let child_node = $nodes.insert(pmt_node! { @($nodes, $values)
+                let child_node = pmt_node! { @($nodes, $values)
                     $child_type { $( $child_tokens )* }
-                }.into());
-                choices[$choice as usize] = $crate::storage::NodeRef::new(child_node);
+                }.into();
+                choices[$choice as usize] = $crate::storage::NodeRef::new($nodes.insert(child_node));
             )*
             choices
         })
@@ -49,11 +49,10 @@ macro_rules! pmt_node {
         let mut branch_node = $crate::nodes::BranchNode::<Vec<u8>, _, sha3::Keccak256>::new({
             let mut choices = [$crate::storage::NodeRef::default(); 16];
             $(
-                choices[$choice as usize] = $crate::storage::NodeRef::new($nodes.insert(
-                    pmt_node! { @($nodes, $values)
-                        $child_type { $( $child_tokens )* }
-                    }.into()
-                ));
+                let child_node = pmt_node! { @($nodes, $values)
+                    $child_type { $( $child_tokens )* }
+                }.into();
+                choices[$choice as usize] = $crate::storage::NodeRef::new($nodes.insert(child_node));
             )*
             choices
         });
@@ -1,35 +1,42 @@
-use super::LeafNode;
+use super::{ExtensionNode, LeafNode};
 use crate::{
-    hashing::{NodeHash, NodeHashRef, NodeHasher},
-    nibble::NibbleSlice,
+    hashing::{NodeEncoder, NodeHash, NodeHashRef, NodeHasher},
+    nibble::{Nibble, NibbleSlice, NibbleVec},
     node::{InsertAction, Node},
-    NodeRef, NodesStorage, ValueRef, ValuesStorage,
+    storage::TryGet,
+    Encodable, NodeRef, NodesStorage, ValueRef, ValuesStorage,
 };
 use digest::Digest;
-use std::marker::PhantomData;
+use std::{marker::PhantomData, mem::take};
 
 #[derive(Clone, Debug)]
 pub struct BranchNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     // The node zero is always the root, which cannot be a child.
     pub(crate) choices: [NodeRef; 16],
     pub(crate) value_ref: ValueRef,
 
-    hash: NodeHash<H>,
+    pub(crate) hash: NodeHash<H>,
     phantom: PhantomData<(P, V, H)>,
 }
 
 impl<P, V, H> BranchNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub(crate) fn new(choices: [NodeRef; 16]) -> Self {
+        // A branch without children is never valid, even with a value (it'd be a leaf instead).
+        debug_assert!(
+            choices.iter().any(NodeRef::is_valid),
+            "branch without children"
+        );
+
         Self {
             choices,
             value_ref: Default::default(),
@@ -42,6 +49,25 @@ where
         self.value_ref = new_value_ref;
     }
 
+    /// Return the children references, indexed by nibble (invalid if there's no child).
+    pub fn choices(&self) -> &[NodeRef; 16] {
+        &self.choices
+    }
+
+    /// Return the reference to the branch's own value (invalid if there's none).
+    pub fn value_ref(&self) -> ValueRef {
+        self.value_ref
+    }
+
+    /// Return a bitmask of the occupied choices, where bit `n` is set if choice `n` has a child.
+    pub fn occupied_mask(&self) -> u16 {
+        self.choices
+            .iter()
+            .enumerate()
+            .filter(|(_, choice)| choice.is_valid())
+            .fold(0, |mask, (index, _)| mask | (1 << index))
+    }
+
     pub fn get<'a>(
         &self,
         nodes: &'a NodesStorage<P, V, H>,
@@ -51,33 +77,33 @@ where
         // If path is at the end, return to its own value if present.
         // Otherwise, check the corresponding choice and delegate accordingly if present.
 
-        path.next()
-            .map(usize::from)
-            .and_then(|choice| {
+        match path.next().map(usize::from) {
+            Some(choice) => {
                 // Delegate to children if present.
                 let child_ref = self.choices[choice];
                 if child_ref.is_valid() {
                     let child_node = nodes
-                        .get(*child_ref)
+                        .try_get(*child_ref)
                         .expect("inconsistent internal tree structure");
 
                     child_node.get(nodes, values, path)
                 } else {
                     None
                 }
-            })
-            .or_else(|| {
+            }
+            None => {
                 // Return internal value if present.
                 if self.value_ref.is_valid() {
                     let (_, value) = values
-                        .get(*self.value_ref)
+                        .try_get(*self.value_ref)
                         .expect("inconsistent internal tree structure");
 
                     Some(value)
                 } else {
                     None
                 }
-            })
+            }
+        }
     }
 
     pub(crate) fn insert(
@@ -89,8 +115,6 @@ where
         // If path is at the end, insert or replace its own value.
         // Otherwise, check the corresponding choice and insert or delegate accordingly.
 
-        self.hash.mark_as_dirty();
-
         let insert_action = match path.next() {
             Some(choice) => match &mut self.choices[choice as usize] {
                 choice_ref if !choice_ref.is_valid() => {
@@ -119,82 +143,175 @@ where
             }
         };
 
+        // Replacements are marked as dirty by the tree, only if the value actually changes.
+        if !matches!(insert_action, InsertAction::Replace(_)) {
+            self.hash.mark_as_dirty();
+        }
+
         (self.into(), insert_action)
     }
 
+    pub(crate) fn remove(
+        mut self,
+        nodes: &mut NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        mut path: NibbleSlice,
+    ) -> (Option<Node<P, V, H>>, Option<ValueRef>) {
+        // Possible flow paths (the reverse of the insertions):
+        //   branch { 0 => a, 1 => b } with_value ! -> branch { 0 => a, 1 => b }
+        //   branch { 0 => a } with_value ! -> extension { [0], a } (or a merged extension, or a leaf)
+        //   branch { 0 => a, 1 => b, 2 => c } -> branch { 0 => a, 1 => b }
+        //   branch { 0 => a, 1 => b } -> extension { [0], a } (or a merged extension, or a leaf)
+        //   branch { 0 => a } with_value v -> leaf { v }
+
+        let offset = path.offset();
+        let value_ref = match path.next() {
+            Some(choice) => {
+                let choice_ref = &mut self.choices[choice as usize];
+                if !choice_ref.is_valid() {
+                    return (Some(self.into()), None);
+                }
+
+                let child_node = nodes
+                    .try_remove(**choice_ref)
+                    .expect("inconsistent internal tree structure");
+
+                let (child_node, value_ref) = child_node.remove(nodes, values, path);
+                *choice_ref = match child_node {
+                    Some(child_node) => NodeRef::new(nodes.insert(child_node)),
+                    None => NodeRef::default(),
+                };
+
+                match value_ref {
+                    Some(value_ref) => value_ref,
+                    None => return (Some(self.into()), None),
+                }
+            }
+            None if self.value_ref.is_valid() => take(&mut self.value_ref),
+            None => return (Some(self.into()), None),
+        };
+
+        self.hash.mark_as_dirty();
+        (Some(self.collapse(nodes, offset)), Some(value_ref))
+    }
+
+    /// Replace the branch by a simpler node if it's left with a single child and no value, or with
+    /// no children.
+    fn collapse(self, nodes: &mut NodesStorage<P, V, H>, offset: usize) -> Node<P, V, H> {
+        let occupied_mask = self.occupied_mask();
+        match occupied_mask.count_ones() {
+            0 => LeafNode::new(self.value_ref).into(),
+            1 if !self.value_ref.is_valid() => {
+                let choice = occupied_mask.trailing_zeros() as u8;
+                let child_node = nodes
+                    .try_remove(*self.choices[choice as usize])
+                    .expect("inconsistent internal tree structure");
+                let choice = Nibble::try_from(choice).expect("invalid nibble");
+
+                match child_node {
+                    Node::Branch(branch_node) => ExtensionNode::new(
+                        NibbleVec::from_nibbles_at(offset, [choice].into_iter()),
+                        NodeRef::new(nodes.insert(branch_node.into())),
+                    )
+                    .into(),
+                    Node::Extension(extension_node) => ExtensionNode::new(
+                        NibbleVec::from_nibbles_at(
+                            offset,
+                            [choice].into_iter().chain(extension_node.prefix.iter()),
+                        ),
+                        extension_node.child_ref,
+                    )
+                    .into(),
+                    Node::Leaf(mut leaf_node) => {
+                        // Leaves only encode the rest of their path, which is now longer.
+                        leaf_node.hash.mark_as_dirty();
+                        leaf_node.into()
+                    }
+                }
+            }
+            _ => self.into(),
+        }
+    }
+
     pub fn compute_hash(
         &self,
         nodes: &NodesStorage<P, V, H>,
         values: &ValuesStorage<P, V>,
         key_offset: usize,
-    ) -> NodeHashRef<H> {
+    ) -> NodeHashRef<'_, H> {
         self.hash.extract_ref().unwrap_or_else(|| {
-            let mut children_len: usize = self
-                .choices
-                .iter()
-                .map(|choice| {
-                    choice
-                        .is_valid()
-                        .then(|| {
-                            let child_node = nodes
-                                .get(**choice)
-                                .expect("inconsistent internal tree structure");
-
-                            let child_hash_ref =
-                                child_node.compute_hash(nodes, values, key_offset + 1);
-                            match child_hash_ref {
-                                NodeHashRef::Inline(x) => x.len(),
-                                NodeHashRef::Hashed(x) => NodeHasher::<H>::bytes_len(x.len(), x[0]),
-                            }
-                        })
-                        .unwrap_or(1)
-                })
-                .sum();
-
-            if self.value_ref.is_valid() {
-                let (_, value) = values
-                    .get(*self.value_ref)
-                    .expect("inconsistent internal tree structure");
-
-                children_len += NodeHasher::<H>::bytes_len(
-                    value.as_ref().len(),
-                    value.as_ref().first().copied().unwrap_or_default(),
-                );
-            } else {
-                children_len += 1;
-            }
-
             let mut hasher = NodeHasher::new(&self.hash);
-            hasher.write_list_header(children_len);
+            self.encode_into(nodes, values, key_offset, &mut hasher);
+            hasher.finalize()
+        })
+    }
 
-            self.choices.iter().for_each(|choice| {
+    pub(crate) fn encode_into(
+        &self,
+        nodes: &NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        key_offset: usize,
+        encoder: &mut impl NodeEncoder,
+    ) {
+        let mut children_len: usize = self
+            .choices
+            .iter()
+            .map(|choice| {
                 if choice.is_valid() {
                     let child_node = nodes
-                        .get(**choice)
+                        .try_get(**choice)
                         .expect("inconsistent internal tree structure");
 
                     let child_hash_ref = child_node.compute_hash(nodes, values, key_offset + 1);
                     match child_hash_ref {
-                        NodeHashRef::Inline(x) => hasher.write_raw(&x),
-                        NodeHashRef::Hashed(x) => hasher.write_bytes(&x),
+                        NodeHashRef::Inline(x) => x.len(),
+                        NodeHashRef::Hashed(x) => NodeHasher::<H>::bytes_len(x.len(), x[0]),
                     }
                 } else {
-                    hasher.write_bytes(&[]);
+                    1
                 }
-            });
+            })
+            .sum();
+
+        if self.value_ref.is_valid() {
+            let (_, value) = values
+                .try_get(*self.value_ref)
+                .expect("inconsistent internal tree structure");
+
+            let value = value.encoded();
+            children_len +=
+                NodeHasher::<H>::bytes_len(value.len(), value.first().copied().unwrap_or_default());
+        } else {
+            children_len += 1;
+        }
+
+        encoder.write_list_header(children_len);
 
-            if self.value_ref.is_valid() {
-                let (_, value) = values
-                    .get(*self.value_ref)
+        self.choices.iter().for_each(|choice| {
+            if choice.is_valid() {
+                let child_node = nodes
+                    .try_get(**choice)
                     .expect("inconsistent internal tree structure");
 
-                hasher.write_bytes(value.as_ref());
+                let child_hash_ref = child_node.compute_hash(nodes, values, key_offset + 1);
+                match child_hash_ref {
+                    NodeHashRef::Inline(x) => encoder.write_raw(&x),
+                    NodeHashRef::Hashed(x) => encoder.write_bytes(&x),
+                }
             } else {
-                hasher.write_bytes(&[]);
+                encoder.write_bytes(&[]);
             }
+        });
 
-            hasher.finalize()
-        })
+        if self.value_ref.is_valid() {
+            let (_, value) = values
+                .try_get(*self.value_ref)
+                .expect("inconsistent internal tree structure");
+
+            encoder.write_bytes(&value.encoded());
+        } else {
+            encoder.write_bytes(&[]);
+        }
     }
 }
 
@@ -238,6 +355,51 @@ mod test {
         );
     }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "branch without children")]
+    fn new_empty() {
+        BranchNode::<Vec<u8>, Vec<u8>, Keccak256>::new([Default::default(); 16]);
+    }
+
+    #[test]
+    fn occupied_mask_empty() {
+        let mut node = BranchNode::<Vec<u8>, Vec<u8>, Keccak256>::new({
+            let mut choices = [Default::default(); 16];
+            choices[0] = NodeRef::new(0);
+            choices
+        });
+        node.choices[0] = Default::default();
+
+        assert_eq!(node.occupied_mask(), 0x0000);
+    }
+
+    #[test]
+    fn occupied_mask_single() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node: BranchNode<_, Vec<u8>, _> = pmt_node! { @(nodes, values)
+            branch {
+                5 => leaf { vec![0x50] => vec![0x50] },
+            }
+        };
+
+        assert_eq!(node.occupied_mask(), 0x0020);
+    }
+
+    #[test]
+    fn occupied_mask_full() {
+        let node = BranchNode::<Vec<u8>, Vec<u8>, Keccak256>::new({
+            let mut choices = [Default::default(); 16];
+            for (index, choice) in choices.iter_mut().enumerate() {
+                *choice = NodeRef::new(index);
+            }
+            choices
+        });
+
+        assert_eq!(node.occupied_mask(), 0xFFFF);
+    }
+
     #[test]
     fn get_some() {
         let (mut nodes, mut values) = pmt_state!(Vec<u8>);
@@ -279,6 +441,29 @@ mod test {
         );
     }
 
+    #[test]
+    fn get_none_with_value() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+            } with_leaf { vec![] => vec![0xAB] }
+        };
+
+        assert_eq!(
+            node.get(&nodes, &values, NibbleSlice::new(&[0x20]))
+                .map(Vec::as_slice),
+            None,
+        );
+        assert_eq!(
+            node.get(&nodes, &values, NibbleSlice::new(&[]))
+                .map(Vec::as_slice),
+            Some([0xAB].as_slice()),
+        );
+    }
+
     #[test]
     fn insert_self() {
         let (mut nodes, mut values) = pmt_state!(Vec<u8>);
@@ -291,12 +476,14 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[]));
-        let _ = match node {
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check node and children.
+        assert_eq!(node.occupied_mask(), 0x0003);
+        assert_eq!(node.choices()[..2], [NodeRef::new(0), NodeRef::new(1)]);
+        assert!(nodes.iter().all(|(_, x)| matches!(x, Node::Leaf(_))));
         assert_eq!(insert_action, InsertAction::InsertSelf);
     }
 
@@ -312,12 +499,17 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x20]));
-        let _ = match node {
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check node and children.
+        assert_eq!(node.occupied_mask(), 0x0007);
+        assert_eq!(
+            node.choices()[..3],
+            [NodeRef::new(0), NodeRef::new(1), NodeRef::new(2)],
+        );
+        assert!(nodes.iter().all(|(_, x)| matches!(x, Node::Leaf(_))));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(2)));
     }
 
@@ -333,17 +525,16 @@ mod test {
         };
 
         // The extension node is ignored since it's irrelevant in this test.
-        let (node, insert_action) = node.insert(&mut nodes, &mut values, {
-            let mut nibble_slice = NibbleSlice::new(&[0x00]);
-            nibble_slice.offset_add(2);
-            nibble_slice
-        });
-        let _ = match node {
+        let (node, insert_action) =
+            node.insert(&mut nodes, &mut values, NibbleSlice::new_offset(&[0x00], 2));
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check node and children.
+        assert_eq!(node.occupied_mask(), 0x0003);
+        assert_eq!(node.choices()[..2], [NodeRef::new(0), NodeRef::new(1)]);
+        assert_eq!(nodes.len(), 2);
         assert_eq!(insert_action, InsertAction::InsertSelf);
     }
 
@@ -456,4 +647,157 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn compute_hash_two_choices_with_value_inline() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                2 => leaf { vec![0x20] => vec![0x20] },
+                4 => leaf { vec![0x40] => vec![0x40] },
+            } with_leaf { vec![] => vec![0x12, 0x34] }
+        };
+
+        assert_eq!(
+            node.compute_hash(&nodes, &values, 0).as_ref(),
+            &[
+                0xD7, 0x80, 0x80, 0xC2, 0x30, 0x20, 0x80, 0xC2, 0x30, 0x40, 0x80, 0x80, 0x80, 0x80,
+                0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x82, 0x12, 0x34,
+            ],
+        );
+    }
+
+    #[test]
+    fn compute_hash_two_choices_with_value_long() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                2 => leaf { vec![0x20] => vec![0x20] },
+                4 => leaf { vec![0x40] => vec![0x40] },
+            } with_leaf { vec![] => vec![0xAB; 60] }
+        };
+
+        let mut encoding = vec![
+            0xF8, 0x52, 0x80, 0x80, 0xC2, 0x30, 0x20, 0x80, 0xC2, 0x30, 0x40,
+        ];
+        encoding.extend_from_slice(&[0x80; 11]);
+        encoding.extend_from_slice(&[0xB8, 0x3C]);
+        encoding.extend_from_slice(&[0xAB; 60]);
+
+        assert_eq!(
+            node.compute_hash(&nodes, &values, 0).as_ref(),
+            Keccak256::digest(&encoding).as_slice(),
+        );
+    }
+
+    #[test]
+    fn remove_choice() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+                2 => leaf { vec![0x20] => vec![0x56, 0x78, 0x9A, 0xBC] },
+            }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x10]));
+        let node = match node {
+            Some(Node::Branch(x)) => x,
+            _ => panic!("expected a branch node"),
+        };
+
+        assert_eq!(node.occupied_mask(), 0x0005);
+        assert!(node.hash.is_dirty());
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(value_ref, Some(ValueRef::new(1)));
+    }
+
+    #[test]
+    fn remove_none() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+            }
+        };
+
+        for path in [&[0x20][..], &[0x01], &[]] {
+            let (node, value_ref) =
+                node.clone()
+                    .remove(&mut nodes, &values, NibbleSlice::new(path));
+            assert!(matches!(node, Some(Node::Branch(x)) if x.occupied_mask() == 0x0003));
+            assert_eq!(nodes.len(), 2);
+            assert_eq!(value_ref, None);
+        }
+    }
+
+    #[test]
+    fn remove_collapse_leaf() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+            }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x00]));
+        let node = match node {
+            Some(Node::Leaf(x)) => x,
+            _ => panic!("expected a leaf node"),
+        };
+
+        // The leaf now encodes one more nibble of its path.
+        assert_eq!(node.value_ref(), ValueRef::new(1));
+        assert!(node.hash.is_dirty());
+        assert!(nodes.is_empty());
+        assert_eq!(value_ref, Some(ValueRef::new(0)));
+    }
+
+    #[test]
+    fn remove_self_collapse_extension() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => branch {
+                    0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                    1 => leaf { vec![0x01] => vec![0x34, 0x56, 0x78, 0x9A] },
+                },
+            } with_leaf { vec![] => vec![0x56, 0x78, 0x9A, 0xBC] }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[]));
+        let node = match node {
+            Some(Node::Extension(x)) => x,
+            _ => panic!("expected an extension node"),
+        };
+
+        assert!(node.prefix.iter().eq([Nibble::V0]));
+        assert!(matches!(&nodes[*node.child_ref], Node::Branch(x) if x.occupied_mask() == 0x0003));
+        assert_eq!(value_ref, Some(ValueRef::new(2)));
+    }
+
+    #[test]
+    fn remove_choice_collapse_value() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+            } with_leaf { vec![] => vec![0x34, 0x56, 0x78, 0x9A] }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x00]));
+        assert!(matches!(node, Some(Node::Leaf(x)) if x.value_ref() == ValueRef::new(1)));
+        assert!(nodes.is_empty());
+        assert_eq!(value_ref, Some(ValueRef::new(0)));
+    }
 }
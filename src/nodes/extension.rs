@@ -1,10 +1,11 @@
 use super::BranchNode;
 use crate::{
-    hashing::{NodeHash, NodeHashRef, NodeHasher, PathKind},
+    hashing::{NodeEncoder, NodeHash, NodeHashRef, NodeHasher, PathKind},
     nibble::{NibbleSlice, NibbleVec},
     node::{InsertAction, Node},
     nodes::LeafNode,
-    NodeRef, NodesStorage, ValuesStorage,
+    storage::TryGet,
+    Encodable, NodeRef, NodesStorage, ValueRef, ValuesStorage,
 };
 use digest::Digest;
 use std::marker::PhantomData;
@@ -13,7 +14,7 @@ use std::marker::PhantomData;
 pub struct ExtensionNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub(crate) prefix: NibbleVec,
@@ -21,14 +22,14 @@ where
     // inflating `Node`'s size too much.
     pub(crate) child_ref: NodeRef,
 
-    hash: NodeHash<H>,
+    pub(crate) hash: NodeHash<H>,
     phantom: PhantomData<(P, V, H)>,
 }
 
 impl<P, V, H> ExtensionNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub(crate) fn new(prefix: NibbleVec, child_ref: NodeRef) -> Self {
@@ -40,6 +41,16 @@ where
         }
     }
 
+    /// Return the prefix's nibbles.
+    pub fn prefix(&self) -> impl '_ + Iterator<Item = u8> {
+        self.prefix.iter().map(u8::from)
+    }
+
+    /// Return the reference to the child node.
+    pub fn child_ref(&self) -> NodeRef {
+        self.child_ref
+    }
+
     pub fn get<'a>(
         &self,
         nodes: &'a NodesStorage<P, V, H>,
@@ -52,7 +63,7 @@ where
         path.skip_prefix(&self.prefix)
             .then(|| {
                 let child_node = nodes
-                    .get(*self.child_ref)
+                    .try_get(*self.child_ref)
                     .expect("inconsistent internal tree structure");
 
                 child_node.get(nodes, values, path)
@@ -77,8 +88,6 @@ where
         //   extension { [0, 1, 2], child } -> extension { [0, 1], branch { 2 => child } with_value ! }
         //   extension { [0, 1, 2], child } -> extension { [0, 1, 2], child }
 
-        self.hash.mark_as_dirty();
-
         if path.skip_prefix(&self.prefix) {
             let child_node = nodes
                 .try_remove(*self.child_ref)
@@ -87,9 +96,16 @@ where
             let (child_node, insert_action) = child_node.insert(nodes, values, path);
             self.child_ref = NodeRef::new(nodes.insert(child_node));
 
+            // Replacements are marked as dirty by the tree, only if the value actually changes.
             let insert_action = insert_action.quantize_self(self.child_ref);
+            if !matches!(insert_action, InsertAction::Replace(_)) {
+                self.hash.mark_as_dirty();
+            }
+
             (self.into(), insert_action)
         } else {
+            self.hash.mark_as_dirty();
+
             // TODO: Investigate why offset sometimes points after the last nibble in
             //   `self.split_extract_at()` causing an assert to fail.
             let offset = path.clone().count_prefix_vec(&self.prefix);
@@ -137,36 +153,99 @@ where
         }
     }
 
+    pub(crate) fn remove(
+        mut self,
+        nodes: &mut NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        mut path: NibbleSlice,
+    ) -> (Option<Node<P, V, H>>, Option<ValueRef>) {
+        // Possible flow paths (the child is always a branch, which may collapse):
+        //   extension { [0], branch { .. } } -> extension { [0], branch { .. } }
+        //   extension { [0], branch { .. } } -> extension { [0, 1], child } // merged
+        //   extension { [0], branch { .. } } -> leaf { key => value }
+
+        let offset = path.offset();
+        if !path.skip_prefix(&self.prefix) {
+            return (Some(self.into()), None);
+        }
+
+        let child_node = nodes
+            .try_remove(*self.child_ref)
+            .expect("inconsistent internal tree structure");
+
+        let (child_node, value_ref) = child_node.remove(nodes, values, path);
+        let child_node = child_node.expect("inconsistent internal tree structure");
+        let value_ref = match value_ref {
+            Some(value_ref) => value_ref,
+            None => {
+                self.child_ref = NodeRef::new(nodes.insert(child_node));
+                return (Some(self.into()), None);
+            }
+        };
+
+        let node = match child_node {
+            Node::Branch(branch_node) => {
+                self.hash.mark_as_dirty();
+                self.child_ref = NodeRef::new(nodes.insert(branch_node.into()));
+                self.into()
+            }
+            Node::Extension(extension_node) => ExtensionNode::new(
+                NibbleVec::from_nibbles_at(
+                    offset,
+                    self.prefix.iter().chain(extension_node.prefix.iter()),
+                ),
+                extension_node.child_ref,
+            )
+            .into(),
+            Node::Leaf(mut leaf_node) => {
+                // Leaves only encode the rest of their path, which is now longer.
+                leaf_node.hash.mark_as_dirty();
+                leaf_node.into()
+            }
+        };
+
+        (Some(node), Some(value_ref))
+    }
+
     pub fn compute_hash(
         &self,
         nodes: &NodesStorage<P, V, H>,
         values: &ValuesStorage<P, V>,
         key_offset: usize,
-    ) -> NodeHashRef<H> {
+    ) -> NodeHashRef<'_, H> {
         self.hash.extract_ref().unwrap_or_else(|| {
-            let child_node = nodes
-                .get(*self.child_ref)
-                .expect("inconsistent internal tree structure");
-
-            let child_hash_ref =
-                child_node.compute_hash(nodes, values, key_offset + self.prefix.len());
-
-            let prefix_len = NodeHasher::<H>::path_len(self.prefix.len());
-            let child_len = match &child_hash_ref {
-                NodeHashRef::Inline(x) => x.len(),
-                NodeHashRef::Hashed(x) => NodeHasher::<H>::bytes_len(x.len(), x[0]),
-            };
-
             let mut hasher = NodeHasher::new(&self.hash);
-            hasher.write_list_header(prefix_len + child_len);
-            hasher.write_path_vec(&self.prefix, PathKind::Extension);
-            match child_hash_ref {
-                NodeHashRef::Inline(x) => hasher.write_raw(&x),
-                NodeHashRef::Hashed(x) => hasher.write_bytes(&x),
-            }
+            self.encode_into(nodes, values, key_offset, &mut hasher);
             hasher.finalize()
         })
     }
+
+    pub(crate) fn encode_into(
+        &self,
+        nodes: &NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        key_offset: usize,
+        encoder: &mut impl NodeEncoder,
+    ) {
+        let child_node = nodes
+            .try_get(*self.child_ref)
+            .expect("inconsistent internal tree structure");
+
+        let child_hash_ref = child_node.compute_hash(nodes, values, key_offset + self.prefix.len());
+
+        let prefix_len = NodeHasher::<H>::path_len(self.prefix.len());
+        let child_len = match &child_hash_ref {
+            NodeHashRef::Inline(x) => x.len(),
+            NodeHashRef::Hashed(x) => NodeHasher::<H>::bytes_len(x.len(), x[0]),
+        };
+
+        encoder.write_list_header(prefix_len + child_len);
+        encoder.write_path_vec(&self.prefix, PathKind::Extension);
+        match child_hash_ref {
+            NodeHashRef::Inline(x) => encoder.write_raw(&x),
+            NodeHashRef::Hashed(x) => encoder.write_bytes(&x),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -242,8 +321,14 @@ mod test {
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check children.
-        assert!(node.prefix.iter().eq([Nibble::V0].into_iter()));
+        assert!(node.prefix().eq([0]));
+        let child_node = match &nodes[*node.child_ref()] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(child_node.occupied_mask(), 0x0007);
+        assert_eq!(child_node.choices()[2], NodeRef::new(2));
+        assert!(matches!(nodes[2], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(2)));
     }
 
@@ -259,12 +344,16 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x10]));
-        let _ = match node {
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check node and children.
+        // The extension is consumed entirely, so its child goes straight into the new branch.
+        assert_eq!(node.occupied_mask(), 0x0003);
+        assert_eq!(node.choices()[..2], [NodeRef::new(2), NodeRef::new(3)]);
+        assert!(matches!(&nodes[2], Node::Branch(x) if x.occupied_mask() == 0x0003));
+        assert!(matches!(nodes[3], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(3)));
     }
 
@@ -280,12 +369,22 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x10]));
-        let _ = match node {
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check node and children.
+        // The rest of the prefix becomes a new extension under the new branch.
+        assert_eq!(node.occupied_mask(), 0x0003);
+        assert_eq!(node.choices()[..2], [NodeRef::new(3), NodeRef::new(4)]);
+        let child_node = match &nodes[3] {
+            Node::Extension(x) => x,
+            _ => panic!("expected an extension node"),
+        };
+        assert!(child_node.prefix().eq([0]));
+        assert_eq!(child_node.child_ref(), NodeRef::new(2));
+        assert!(matches!(nodes[2], Node::Branch(_)));
+        assert!(matches!(nodes[4], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(4)));
     }
 
@@ -301,12 +400,24 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x01]));
-        let _ = match node {
+        let node = match node {
             Node::Extension(x) => x,
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check node and children.
+        // The prefix is split before its last nibble, which leads to the old child.
+        assert!(node.prefix().eq([0]));
+        let child_node = match &nodes[*node.child_ref()] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(child_node.occupied_mask(), 0x0003);
+        assert_eq!(
+            child_node.choices()[..2],
+            [NodeRef::new(2), NodeRef::new(3)]
+        );
+        assert!(matches!(nodes[2], Node::Branch(_)));
+        assert!(matches!(nodes[3], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(3)));
     }
 
@@ -315,20 +426,39 @@ mod test {
         let (mut nodes, mut values) = pmt_state!(Vec<u8>);
 
         let node = pmt_node! { @(nodes, values)
-            extension { [0, 0], branch {
+            extension { [0, 0, 0], branch {
                 0 => leaf { vec![0x00, 0x00] => vec![0x12, 0x34, 0x56, 0x78] },
-                1 => leaf { vec![0x00, 0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+                1 => leaf { vec![0x00, 0x01] => vec![0x34, 0x56, 0x78, 0x9A] },
             } }
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x01]));
-        let _ = match node {
+        let node = match node {
             Node::Extension(x) => x,
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check node and children.
-        assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(3)));
+        // The prefix is split in the middle: the rest of it becomes a new extension under the new
+        // branch.
+        assert!(node.prefix().eq([0]));
+        let child_node = match &nodes[*node.child_ref()] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(child_node.occupied_mask(), 0x0003);
+        assert_eq!(
+            child_node.choices()[..2],
+            [NodeRef::new(3), NodeRef::new(4)]
+        );
+        let grandchild_node = match &nodes[3] {
+            Node::Extension(x) => x,
+            _ => panic!("expected an extension node"),
+        };
+        assert!(grandchild_node.prefix().eq([0]));
+        assert_eq!(grandchild_node.child_ref(), NodeRef::new(2));
+        assert!(matches!(nodes[2], Node::Branch(_)));
+        assert!(matches!(nodes[4], Node::Leaf(_)));
+        assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(4)));
     }
 
     #[test]
@@ -374,4 +504,93 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn remove_passthrough() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            extension { [0], branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x01] => vec![0x34, 0x56, 0x78, 0x9A] },
+                2 => leaf { vec![0x02] => vec![0x56, 0x78, 0x9A, 0xBC] },
+            } }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x02]));
+        let node = match node {
+            Some(Node::Extension(x)) => x,
+            _ => panic!("expected an extension node"),
+        };
+
+        assert!(node.prefix.iter().eq([Nibble::V0]));
+        assert!(node.hash.is_dirty());
+        assert!(matches!(&nodes[*node.child_ref], Node::Branch(x) if x.occupied_mask() == 0x0003));
+        assert_eq!(value_ref, Some(ValueRef::new(2)));
+    }
+
+    #[test]
+    fn remove_none() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            extension { [0], branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x01] => vec![0x34, 0x56, 0x78, 0x9A] },
+            } }
+        };
+
+        for path in [&[0x10][..], &[0x02]] {
+            let (node, value_ref) =
+                node.clone()
+                    .remove(&mut nodes, &values, NibbleSlice::new(path));
+            assert!(matches!(node, Some(Node::Extension(_))));
+            assert_eq!(nodes.len(), 3);
+            assert_eq!(value_ref, None);
+        }
+    }
+
+    #[test]
+    fn remove_collapse_leaf() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            extension { [0], branch {
+                0 => leaf { vec![0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                1 => leaf { vec![0x01] => vec![0x34, 0x56, 0x78, 0x9A] },
+            } }
+        };
+
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x00]));
+        assert!(matches!(node, Some(Node::Leaf(x)) if x.value_ref() == ValueRef::new(1)));
+        assert!(nodes.is_empty());
+        assert_eq!(value_ref, Some(ValueRef::new(0)));
+    }
+
+    #[test]
+    fn remove_merge_extension() {
+        let (mut nodes, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            extension { [0], branch {
+                0 => branch {
+                    0 => leaf { vec![0x00, 0x00] => vec![0x12, 0x34, 0x56, 0x78] },
+                    1 => leaf { vec![0x00, 0x10] => vec![0x34, 0x56, 0x78, 0x9A] },
+                },
+                1 => leaf { vec![0x01] => vec![0x56, 0x78, 0x9A, 0xBC] },
+            } }
+        };
+
+        // The inner branch collapses into an extension, which is merged into this one.
+        let (node, value_ref) = node.remove(&mut nodes, &values, NibbleSlice::new(&[0x01]));
+        let node = match node {
+            Some(Node::Extension(x)) => x,
+            _ => panic!("expected an extension node"),
+        };
+
+        assert!(node.prefix.iter().eq([Nibble::V0, Nibble::V0]));
+        assert!(matches!(&nodes[*node.child_ref], Node::Branch(x) if x.occupied_mask() == 0x0003));
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(value_ref, Some(ValueRef::new(2)));
+    }
 }
@@ -1,30 +1,33 @@
 use super::{BranchNode, ExtensionNode};
 use crate::{
-    hashing::{NodeHash, NodeHashRef, NodeHasher, PathKind},
+    hashing::{
+        EncodedLen, NodeEncoder, NodeHash, NodeHashRef, NodeHasher, PathKind, INLINE_THRESHOLD,
+    },
     nibble::NibbleSlice,
     node::{InsertAction, Node},
-    NodeRef, NodesStorage, ValueRef, ValuesStorage,
+    storage::TryGet,
+    Encodable, NodeRef, NodesStorage, ValueRef, ValuesStorage,
 };
-use digest::Digest;
+use digest::{Digest, Output};
 use std::marker::PhantomData;
 
 #[derive(Clone, Debug)]
 pub struct LeafNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub(crate) value_ref: ValueRef,
 
-    hash: NodeHash<H>,
+    pub(crate) hash: NodeHash<H>,
     phantom: PhantomData<(P, V, H)>,
 }
 
 impl<P, V, H> LeafNode<P, V, H>
 where
     P: AsRef<[u8]>,
-    V: AsRef<[u8]>,
+    V: Encodable,
     H: Digest,
 {
     pub(crate) fn new(value_ref: ValueRef) -> Self {
@@ -39,6 +42,11 @@ where
         self.value_ref = new_value_ref;
     }
 
+    /// Return the reference to the leaf's path and value.
+    pub fn value_ref(&self) -> ValueRef {
+        self.value_ref
+    }
+
     pub fn get<'a>(
         &self,
         _nodes: &NodesStorage<P, V, H>,
@@ -49,7 +57,7 @@ where
         // Otherwise, no value is present.
 
         let (value_path, value) = values
-            .get(*self.value_ref)
+            .try_get(*self.value_ref)
             .expect("inconsistent internal tree structure");
 
         path.cmp_rest(value_path.as_ref()).then_some(value)
@@ -68,21 +76,21 @@ where
         //   leaf { key => value } -> extension { [0], branch { 0 => leaf { key => value } } with_value leaf { key => value } }
         //   leaf { key => value } -> extension { [0], branch { 0 => leaf { key => value } } with_value leaf { key => value } } // leafs swapped
 
-        self.hash.mark_as_dirty();
-
         let (value_path, _) = values
-            .get(*self.value_ref)
+            .try_get(*self.value_ref)
             .expect("inconsistent internal tree structure");
 
         if path.cmp_rest(value_path.as_ref()) {
+            // The hash is left as is, since the tree marks the path as dirty only if the value
+            // actually changes.
             let value_ref = self.value_ref;
             (self.into(), InsertAction::Replace(value_ref))
         } else {
-            let offset = path.clone().count_prefix_slice(&{
-                let mut value_path = NibbleSlice::new(value_path.as_ref());
-                value_path.offset_add(path.offset());
-                value_path
-            });
+            self.hash.mark_as_dirty();
+
+            let offset = path
+                .clone()
+                .count_prefix_slice(&NibbleSlice::new_offset(value_path.as_ref(), path.offset()));
 
             let mut path_branch = path.clone();
             path_branch.offset_add(offset);
@@ -92,10 +100,12 @@ where
                 (
                     BranchNode::new({
                         let mut choices = [Default::default(); 16];
-                        // TODO: Dedicated method.
+                        // The paths differ (or the new one ends) at `absolute_offset`, so the
+                        // stored path is long enough.
                         choices[NibbleSlice::new(value_path.as_ref())
-                            .nth(absolute_offset)
-                            .unwrap() as usize] = NodeRef::new(nodes.insert(self.into()));
+                            .nibble_at(absolute_offset)
+                            .expect("inconsistent internal tree structure")
+                            as usize] = NodeRef::new(nodes.insert(self.into()));
                         choices
                     }),
                     InsertAction::InsertSelf,
@@ -116,10 +126,12 @@ where
                 (
                     BranchNode::new({
                         let mut choices = [Default::default(); 16];
-                        // TODO: Dedicated method.
+                        // The paths differ (or the new one ends) at `absolute_offset`, so the
+                        // stored path is long enough.
                         choices[NibbleSlice::new(value_path.as_ref())
-                            .nth(absolute_offset)
-                            .unwrap() as usize] = NodeRef::new(nodes.insert(self.into()));
+                            .nibble_at(absolute_offset)
+                            .expect("inconsistent internal tree structure")
+                            as usize] = NodeRef::new(nodes.insert(self.into()));
                         choices[path_branch.next().unwrap() as usize] = NodeRef::new(child_ref);
                         choices
                     }),
@@ -140,41 +152,86 @@ where
         }
     }
 
+    pub(crate) fn remove(
+        self,
+        values: &ValuesStorage<P, V>,
+        path: NibbleSlice,
+    ) -> (Option<Node<P, V, H>>, Option<ValueRef>) {
+        let (value_path, _) = values
+            .try_get(*self.value_ref)
+            .expect("inconsistent internal tree structure");
+
+        if path.cmp_rest(value_path.as_ref()) {
+            (None, Some(self.value_ref))
+        } else {
+            (Some(self.into()), None)
+        }
+    }
+
     pub fn compute_hash(
         &self,
-        _nodes: &NodesStorage<P, V, H>,
+        nodes: &NodesStorage<P, V, H>,
         values: &ValuesStorage<P, V>,
         key_offset: usize,
-    ) -> NodeHashRef<H> {
+    ) -> NodeHashRef<'_, H> {
         self.hash.extract_ref().unwrap_or_else(|| {
-            let (key, value) = values
-                .get(*self.value_ref)
-                .expect("inconsistent internal tree structure");
-
-            let key_len = NodeHasher::<H>::path_len({
-                let mut key_slice = NibbleSlice::new(key.as_ref());
-                key_slice.offset_add(key_offset);
-                key_slice.len()
-            });
-            let value_len = NodeHasher::<H>::bytes_len(
-                value.as_ref().len(),
-                value.as_ref().first().copied().unwrap_or_default(),
-            );
-
             let mut hasher = NodeHasher::new(&self.hash);
-            hasher.write_list_header(key_len + value_len);
-            hasher.write_path_slice(
-                &{
-                    let mut key_slice = NibbleSlice::new(key.as_ref());
-                    key_slice.offset_add(key_offset);
-                    key_slice
-                },
-                PathKind::Leaf,
-            );
-            hasher.write_bytes(value.as_ref());
+            self.encode_into(nodes, values, key_offset, &mut hasher);
             hasher.finalize()
         })
     }
+
+    /// Store a known hash for the leaf, skipping its computation.
+    ///
+    /// Leaves short enough to be inlined have no hash, so it's ignored in that case. The hash is
+    /// verified in debug builds.
+    pub(crate) fn preset_hash(
+        &self,
+        nodes: &NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        key_offset: usize,
+        hash: &Output<H>,
+    ) {
+        let mut encoded_len = EncodedLen::default();
+        self.encode_into(nodes, values, key_offset, &mut encoded_len);
+        if encoded_len.0 < INLINE_THRESHOLD {
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            let mut encoding = Vec::new();
+            self.encode_into(nodes, values, key_offset, &mut encoding);
+            assert_eq!(&H::digest(encoding), hash, "invalid precomputed leaf hash");
+        }
+
+        self.hash.preset(hash);
+    }
+
+    pub(crate) fn encode_into(
+        &self,
+        _nodes: &NodesStorage<P, V, H>,
+        values: &ValuesStorage<P, V>,
+        key_offset: usize,
+        encoder: &mut impl NodeEncoder,
+    ) {
+        let (key, value) = values
+            .try_get(*self.value_ref)
+            .expect("inconsistent internal tree structure");
+
+        let key_len =
+            NodeHasher::<H>::path_len(NibbleSlice::new_offset(key.as_ref(), key_offset).len());
+        let value = value.encoded();
+        let value_len =
+            NodeHasher::<H>::bytes_len(value.len(), value.first().copied().unwrap_or_default());
+
+        encoder.write_list_header(key_len + value_len);
+        encoder.write_path_slice(
+            &NibbleSlice::new_offset(key.as_ref(), key_offset),
+            PathKind::Leaf,
+        );
+        encoder.write_bytes(&value);
+    }
 }
 
 #[cfg(test)]
@@ -247,12 +304,17 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x22]));
-        let _ = match node {
+        let node = match node {
             Node::Branch(x) => x,
             _ => panic!("expected a branch node"),
         };
 
-        // TODO: Check branch.
+        assert_eq!(node.occupied_mask(), 0x0006);
+        assert_eq!(node.choices()[1], NodeRef::new(1));
+        assert_eq!(node.choices()[2], NodeRef::new(0));
+        assert!(!node.value_ref().is_valid());
+        assert!(matches!(&nodes[1], Node::Leaf(x) if x.value_ref() == ValueRef::new(0)));
+        assert!(matches!(&nodes[0], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(0)));
     }
 
@@ -265,12 +327,23 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x13]));
-        let _ = match node {
+        let node = match node {
             Node::Extension(x) => x,
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check extension (and child branch).
+        assert_eq!(node.prefix().collect::<Vec<_>>(), [0x1]);
+        assert_eq!(node.child_ref(), NodeRef::new(2));
+
+        let branch_node = match &nodes[2] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(branch_node.occupied_mask(), 0x000C);
+        assert_eq!(branch_node.choices()[2], NodeRef::new(1));
+        assert_eq!(branch_node.choices()[3], NodeRef::new(0));
+        assert!(!branch_node.value_ref().is_valid());
+        assert!(matches!(&nodes[1], Node::Leaf(x) if x.value_ref() == ValueRef::new(0)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(0)));
     }
 
@@ -284,12 +357,22 @@ mod test {
 
         let (node, insert_action) =
             node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x12, 0x34]));
-        let _ = match node {
+        let node = match node {
             Node::Extension(x) => x,
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check extension (and children).
+        assert_eq!(node.prefix().collect::<Vec<_>>(), [0x1, 0x2]);
+        assert_eq!(node.child_ref(), NodeRef::new(1));
+
+        let branch_node = match &nodes[1] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(branch_node.occupied_mask(), 0x0008);
+        assert_eq!(branch_node.choices()[3], NodeRef::new(0));
+        assert_eq!(branch_node.value_ref(), ValueRef::new(0));
+        assert!(matches!(&nodes[0], Node::Leaf(_)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(0)));
     }
 
@@ -302,12 +385,22 @@ mod test {
         };
 
         let (node, insert_action) = node.insert(&mut nodes, &mut values, NibbleSlice::new(&[0x12]));
-        let _ = match node {
+        let node = match node {
             Node::Extension(x) => x,
             _ => panic!("expected an extension node"),
         };
 
-        // TODO: Check extension (and children).
+        assert_eq!(node.prefix().collect::<Vec<_>>(), [0x1, 0x2]);
+        assert_eq!(node.child_ref(), NodeRef::new(1));
+
+        let branch_node = match &nodes[1] {
+            Node::Branch(x) => x,
+            _ => panic!("expected a branch node"),
+        };
+        assert_eq!(branch_node.occupied_mask(), 0x0008);
+        assert_eq!(branch_node.choices()[3], NodeRef::new(0));
+        assert!(!branch_node.value_ref().is_valid());
+        assert!(matches!(&nodes[0], Node::Leaf(x) if x.value_ref() == ValueRef::new(0)));
         assert_eq!(insert_action, InsertAction::Insert(NodeRef::new(1)));
     }
 
@@ -352,4 +445,30 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn remove_some() {
+        let (_, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            leaf { vec![0x12] => vec![0x12, 0x34, 0x56, 0x78] }
+        };
+
+        let (node, value_ref) = node.remove(&values, NibbleSlice::new(&[0x12]));
+        assert!(node.is_none());
+        assert_eq!(value_ref, Some(ValueRef::new(0)));
+    }
+
+    #[test]
+    fn remove_none() {
+        let (_, mut values) = pmt_state!(Vec<u8>);
+
+        let node = pmt_node! { @(nodes, values)
+            leaf { vec![0x12] => vec![0x12, 0x34, 0x56, 0x78] }
+        };
+
+        let (node, value_ref) = node.remove(&values, NibbleSlice::new(&[0x12, 0x34]));
+        assert!(matches!(node, Some(Node::Leaf(x)) if x.value_ref() == ValueRef::new(0)));
+        assert_eq!(value_ref, None);
+    }
 }
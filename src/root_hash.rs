@@ -0,0 +1,285 @@
+use digest::{Digest, Output};
+use std::{
+    cmp::Ordering,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter, LowerHex},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+/// The root hash of a tree.
+///
+/// Formatted (and parsed) as `0x`-prefixed hexadecimal.
+pub struct RootHash<H>(Output<H>)
+where
+    H: Digest;
+
+impl<H> RootHash<H>
+where
+    H: Digest,
+{
+    /// Return the hash's bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Return the underlying digest output.
+    pub fn into_inner(self) -> Output<H> {
+        self.0
+    }
+}
+
+impl<H> From<Output<H>> for RootHash<H>
+where
+    H: Digest,
+{
+    fn from(value: Output<H>) -> Self {
+        Self(value)
+    }
+}
+
+impl<H> AsRef<[u8]> for RootHash<H>
+where
+    H: Digest,
+{
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<H> Clone for RootHash<H>
+where
+    H: Digest,
+{
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<H> Copy for RootHash<H>
+where
+    H: Digest,
+    Output<H>: Copy,
+{
+}
+
+impl<H> PartialEq for RootHash<H>
+where
+    H: Digest,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<H> Eq for RootHash<H> where H: Digest {}
+
+impl<H> PartialOrd for RootHash<H>
+where
+    H: Digest,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<H> Ord for RootHash<H>
+where
+    H: Digest,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl<H> Hash for RootHash<H>
+where
+    H: Digest,
+{
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.0.hash(state);
+    }
+}
+
+impl<H> Debug for RootHash<H>
+where
+    H: Digest,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RootHash({self})")
+    }
+}
+
+impl<H> Display for RootHash<H>
+where
+    H: Digest,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:#x}")
+    }
+}
+
+impl<H> LowerHex for RootHash<H>
+where
+    H: Digest,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+
+        self.0.iter().try_for_each(|x| write!(f, "{x:02x}"))
+    }
+}
+
+impl<H> FromStr for RootHash<H>
+where
+    H: Digest,
+{
+    type Err = ParseRootHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("0x").unwrap_or(s).as_bytes();
+
+        let mut value = Output::<H>::default();
+        if s.len() != 2 * value.len() {
+            return Err(ParseRootHashError::InvalidLength);
+        }
+
+        for (target, chunk) in value.iter_mut().zip(s.chunks_exact(2)) {
+            let nibble = |x: u8| {
+                (x as char)
+                    .to_digit(16)
+                    .ok_or(ParseRootHashError::InvalidCharacter)
+            };
+
+            *target = ((nibble(chunk[0])? << 4) | nibble(chunk[1])?) as u8;
+        }
+
+        Ok(Self(value))
+    }
+}
+
+/// Error returned when parsing a `RootHash`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseRootHashError {
+    /// The string doesn't have exactly two hex digits per hash byte.
+    InvalidLength,
+    /// The string contains a non-hexadecimal character.
+    InvalidCharacter,
+}
+
+impl Display for ParseRootHashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseRootHashError::InvalidLength => write!(f, "invalid root hash length"),
+            ParseRootHashError::InvalidCharacter => write!(f, "invalid root hash character"),
+        }
+    }
+}
+
+impl Error for ParseRootHashError {}
+
+/// Error returned when the root hash after an insertion is not the expected one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RootMismatch {
+    /// The expected root hash.
+    pub expected: Vec<u8>,
+    /// The tree's actual root hash.
+    pub actual: Vec<u8>,
+}
+
+impl Display for RootMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "root hash mismatch: expected 0x")?;
+        self.expected
+            .iter()
+            .try_for_each(|x| write!(f, "{x:02x}"))?;
+        write!(f, ", got 0x")?;
+        self.actual.iter().try_for_each(|x| write!(f, "{x:02x}"))
+    }
+}
+
+impl Error for RootMismatch {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+    use sha3::Keccak256;
+    use std::collections::{BTreeSet, HashSet};
+
+    const EMPTY: &str = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+
+    #[test]
+    fn display_hex() {
+        let hash = RootHash::<Keccak256>::from(Keccak256::digest([0x80]));
+
+        assert_eq!(hash.to_string(), EMPTY);
+        assert_eq!(format!("{hash:x}"), &EMPTY[2..]);
+        assert_eq!(format!("{hash:?}"), format!("RootHash({EMPTY})"));
+    }
+
+    #[test]
+    fn from_str_round_trip() {
+        let hash: RootHash<Keccak256> = EMPTY.parse().unwrap();
+
+        assert_eq!(
+            hash.as_slice(),
+            hex!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+        );
+        assert_eq!(hash.to_string(), EMPTY);
+        assert_eq!(EMPTY[2..].to_uppercase().parse(), Ok(hash));
+    }
+
+    #[test]
+    fn from_str_invalid() {
+        assert_eq!(
+            "0x56e8".parse::<RootHash<Keccak256>>(),
+            Err(ParseRootHashError::InvalidLength),
+        );
+        assert_eq!(
+            EMPTY.replace('e', "g").parse::<RootHash<Keccak256>>(),
+            Err(ParseRootHashError::InvalidCharacter),
+        );
+    }
+
+    #[test]
+    fn equality() {
+        let a = RootHash::<Keccak256>::from(Keccak256::digest([0x80]));
+        let b = RootHash::<Keccak256>::from(Keccak256::digest([0x80]));
+        let c = RootHash::<Keccak256>::from(Keccak256::digest([0x81]));
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn root_mismatch_display() {
+        let error = RootMismatch {
+            expected: vec![0x01, 0x23],
+            actual: vec![0xAB, 0xCD],
+        };
+
+        assert_eq!(
+            error.to_string(),
+            "root hash mismatch: expected 0x0123, got 0xabcd",
+        );
+    }
+
+    #[test]
+    fn map_keys() {
+        let a = RootHash::<Keccak256>::from(Keccak256::digest([0x80]));
+        let b = RootHash::<Keccak256>::from(Keccak256::digest([0x81]));
+        let c = RootHash::<Keccak256>::from(Keccak256::digest([0x82]));
+
+        let hash_set = [a, b, c, a, b].into_iter().collect::<HashSet<_>>();
+        assert_eq!(hash_set.len(), 3);
+        assert!(hash_set.contains(&a) && hash_set.contains(&b) && hash_set.contains(&c));
+
+        let btree_set = [c, a, b, c].into_iter().collect::<BTreeSet<_>>();
+        let mut expected = vec![a, b, c];
+        expected.sort_by(|x, y| x.as_slice().cmp(y.as_slice()));
+        assert_eq!(btree_set.into_iter().collect::<Vec<_>>(), expected);
+    }
+}
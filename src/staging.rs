@@ -0,0 +1,82 @@
+use std::sync::Mutex;
+
+/// Buffer collecting insertions from multiple threads, to be applied later to a tree.
+///
+/// When a path is staged multiple times, the last staged value wins.
+///
+/// It's not lock-free: the entries are kept behind a mutex, which every `push()` takes briefly,
+/// so threads staging at once contend for it.
+#[derive(Debug, Default)]
+pub struct StagingBuffer<P, V> {
+    entries: Mutex<Vec<(P, V)>>,
+}
+
+impl<P, V> StagingBuffer<P, V>
+where
+    P: AsRef<[u8]>,
+{
+    /// Create an empty staging buffer.
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Stage an insertion.
+    pub fn push(&self, path: P, value: V) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((path, value));
+    }
+
+    /// Return the number of staged insertions (including duplicates).
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Return whether there are no staged insertions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the staged insertions sorted by path, keeping only the last one for each path.
+    pub(crate) fn into_sorted(self) -> Vec<(P, V)> {
+        let mut entries = self.entries.into_inner().unwrap_or_else(|e| e.into_inner());
+
+        // The sort is stable, therefore duplicates remain in staging order.
+        entries.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+
+        let mut sorted_entries: Vec<(P, V)> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match sorted_entries.last_mut() {
+                Some(last_entry) if last_entry.0.as_ref() == entry.0.as_ref() => {
+                    *last_entry = entry
+                }
+                _ => sorted_entries.push(entry),
+            }
+        }
+
+        sorted_entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn into_sorted_last_wins() {
+        let staging = StagingBuffer::new();
+        staging.push(vec![0x20], 1);
+        staging.push(vec![0x10], 2);
+        staging.push(vec![0x20], 3);
+        staging.push(vec![0x10, 0x00], 4);
+        assert_eq!(staging.len(), 4);
+
+        assert_eq!(
+            staging.into_sorted(),
+            [(vec![0x10], 2), (vec![0x10, 0x00], 4), (vec![0x20], 3)],
+        );
+    }
+}
@@ -1,22 +1,82 @@
 use crate::node::Node;
 use slab::Slab;
-use std::ops::Deref;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    ops::Deref,
+};
 
 const INVALID_REF: usize = usize::MAX;
 
 pub type NodesStorage<P, V, H> = Slab<Node<P, V, H>>;
 pub type ValuesStorage<P, V> = Slab<(P, V)>;
 
+/// Error returned when an index doesn't point to a stored item.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct InvalidRef(pub usize);
+
+impl Display for InvalidRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "no stored item at index {}", self.0)
+    }
+}
+
+impl Error for InvalidRef {}
+
+/// Error returned when an insertion would need more nodes than allowed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NodeBudgetExceeded {
+    /// The maximum number of nodes allowed.
+    pub max_nodes: usize,
+    /// The number of nodes the tree would have after the insertion.
+    pub required: usize,
+}
+
+impl Display for NodeBudgetExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node budget exceeded: {} nodes required, {} allowed",
+            self.required, self.max_nodes,
+        )
+    }
+}
+
+impl Error for NodeBudgetExceeded {}
+
+/// Lookups into the storages reporting the offending index on failure.
+pub(crate) trait TryGet<T> {
+    fn try_get(&self, index: usize) -> Result<&T, InvalidRef>;
+}
+
+impl<T> TryGet<T> for Slab<T> {
+    fn try_get(&self, index: usize) -> Result<&T, InvalidRef> {
+        self.get(index).ok_or(InvalidRef(index))
+    }
+}
+
+/// Reference to a node within the tree's nodes storage.
+///
+/// It's a distinct type from `ValueRef`, so they can't be mixed up:
+///
+/// ```compile_fail
+/// use patricia_merkle_tree::{NodeRef, ValueRef};
+///
+/// fn take_node_ref(_node_ref: NodeRef) {}
+/// take_node_ref(ValueRef::new(0));
+/// ```
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[repr(transparent)]
 pub struct NodeRef(usize);
 
 impl NodeRef {
+    #[must_use]
     pub fn new(value: usize) -> Self {
         assert_ne!(value, INVALID_REF);
         Self(value)
     }
 
+    #[must_use]
     pub fn is_valid(&self) -> bool {
         self.0 != INVALID_REF
     }
@@ -36,16 +96,27 @@ impl Deref for NodeRef {
     }
 }
 
+/// Reference to a path and value pair within the tree's values storage.
+///
+/// It's a distinct type from `NodeRef`, so they can't be mixed up:
+///
+/// ```compile_fail
+/// use patricia_merkle_tree::{NodeRef, ValueRef};
+///
+/// let value_ref: ValueRef = NodeRef::new(0);
+/// ```
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[repr(transparent)]
 pub struct ValueRef(usize);
 
 impl ValueRef {
+    #[must_use]
     pub fn new(value: usize) -> Self {
         assert_ne!(value, INVALID_REF);
         Self(value)
     }
 
+    #[must_use]
     pub fn is_valid(&self) -> bool {
         self.0 != INVALID_REF
     }
@@ -64,3 +135,22 @@ impl Deref for ValueRef {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn try_get() {
+        let mut storage = Slab::new();
+        let index = storage.insert(0x12);
+
+        assert_eq!(storage.try_get(index), Ok(&0x12));
+        assert_eq!(storage.try_get(index + 1), Err(InvalidRef(index + 1)));
+        assert_eq!(
+            storage.try_get(*ValueRef::default()),
+            Err(InvalidRef(usize::MAX))
+        );
+        assert_eq!(InvalidRef(5).to_string(), "no stored item at index 5",);
+    }
+}
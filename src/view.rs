@@ -0,0 +1,146 @@
+use crate::{
+    nibble::NibbleSlice, node::Node, Encodable, Iter, NodeRef, NodesStorage, ValuesStorage,
+};
+use digest::Digest;
+
+/// Read-only view of a tree, borrowing its storage.
+///
+/// It's cheap to copy and can be shared to answer queries without requiring `&mut` access.
+pub struct RootView<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    nodes: &'a NodesStorage<P, V, H>,
+    values: &'a ValuesStorage<P, V>,
+    root_ref: NodeRef,
+}
+
+impl<'a, P, V, H> RootView<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    pub(crate) fn new(
+        nodes: &'a NodesStorage<P, V, H>,
+        values: &'a ValuesStorage<P, V>,
+        root_ref: NodeRef,
+    ) -> Self {
+        Self {
+            nodes,
+            values,
+            root_ref,
+        }
+    }
+
+    /// Return a reference to the root node (invalid if the tree is empty).
+    pub fn root_ref(&self) -> NodeRef {
+        self.root_ref
+    }
+
+    /// Retrieve a value from the tree given its path.
+    pub fn get(&self, path: &P) -> Option<&'a V> {
+        self.get_bytes(path.as_ref())
+    }
+
+    /// Retrieve a value from the tree given its path as a byte slice.
+    pub fn get_bytes(&self, path: &[u8]) -> Option<&'a V> {
+        self.nodes
+            .get(*self.root_ref)
+            .and_then(|root_node| root_node.get(self.nodes, self.values, NibbleSlice::new(path)))
+    }
+
+    /// Return an iterator over the paths and values, sorted by path.
+    pub fn iter(&self) -> Iter<'a, P, V, H> {
+        Iter::new(self.nodes, self.values, self.root_ref)
+    }
+
+    /// Return the RLP encodings of the nodes on the way to a path, from the root down to the node
+    /// holding its value, or to the node proving its absence.
+    pub fn get_proof(&self, path: &[u8]) -> Vec<Vec<u8>> {
+        self.get_with_proof(path).1
+    }
+
+    /// Retrieve a value given its path as a byte slice, along with its proof.
+    pub fn get_with_proof(&self, path: &[u8]) -> (Option<&'a V>, Vec<Vec<u8>>) {
+        let proof_path = self.proof_path(path);
+
+        // The walk ends at the branch or leaf holding the value, if there's any.
+        let value = proof_path.last().and_then(|(node_ref, _)| {
+            let value_ref = match self.node(*node_ref) {
+                Node::Branch(branch_node) => branch_node.value_ref,
+                Node::Extension(_) => return None,
+                Node::Leaf(leaf_node) => leaf_node.value_ref,
+            };
+
+            let (value_path, value) = self.values.get(*value_ref)?;
+            (value_path.as_ref() == path).then_some(value)
+        });
+
+        let proof = proof_path
+            .into_iter()
+            .map(|(node_ref, key_offset)| {
+                let mut encoding = Vec::new();
+                self.node(node_ref)
+                    .encode_into(self.nodes, self.values, key_offset, &mut encoding);
+                encoding
+            })
+            .collect();
+
+        (value, proof)
+    }
+
+    /// Return the nodes on the way to a path along with their nibble offsets, walking them the same
+    /// way as `get_bytes`.
+    pub(crate) fn proof_path(&self, path: &[u8]) -> Vec<(NodeRef, usize)> {
+        let mut proof_path = Vec::new();
+
+        let mut path = NibbleSlice::new(path);
+        let mut node_ref = self.root_ref;
+        while let Some(node) = self.nodes.get(*node_ref) {
+            proof_path.push((node_ref, path.offset()));
+            match node {
+                Node::Branch(branch_node) => match path.next() {
+                    Some(choice) => node_ref = branch_node.choices[choice as usize],
+                    None => break,
+                },
+                Node::Extension(extension_node) => {
+                    if !path.skip_prefix(&extension_node.prefix) {
+                        break;
+                    }
+                    node_ref = extension_node.child_ref;
+                }
+                Node::Leaf(_) => break,
+            }
+        }
+
+        proof_path
+    }
+
+    fn node(&self, node_ref: NodeRef) -> &'a Node<P, V, H> {
+        self.nodes
+            .get(*node_ref)
+            .expect("inconsistent internal tree structure")
+    }
+}
+
+impl<'a, P, V, H> Clone for RootView<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, P, V, H> Copy for RootView<'a, P, V, H>
+where
+    P: AsRef<[u8]>,
+    V: Encodable,
+    H: Digest,
+{
+}